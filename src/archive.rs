@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use crate::error_handling::Loggable;
+use crate::guild_shell::GuildShell;
+use crate::storage::blocking;
+
+/// At most this many messages are archived per guild, the oldest are dropped first.
+const MAX_ARCHIVED_MESSAGES: usize = 5000;
+
+/// What a message looked like before bussy deleted it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageSnapshot {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub author: UserId,
+    pub author_tag: String,
+    pub content: String,
+    pub attachments: Vec<String>,
+    pub embeds: Vec<Value>,
+    #[serde(default)]
+    pub mentions: Vec<UserId>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<&Message> for MessageSnapshot {
+    fn from(message: &Message) -> Self {
+        MessageSnapshot {
+            id: message.id,
+            channel_id: message.channel_id,
+            author: message.author.id,
+            author_tag: message.author.tag(),
+            content: message.content.clone(),
+            attachments: message.attachments.iter().map(|attachment| attachment.url.clone()).collect(),
+            embeds: message.embeds.iter().filter_map(|embed| serde_json::to_value(embed).ok()).collect(),
+            mentions: message.mentions.iter().map(|user| user.id).collect(),
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+impl Display for MessageSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[{}] {} ({}) in channel {}, message {}", self.timestamp.format("%Y-%m-%d %H:%M:%S UTC"), self.author_tag, self.author,
+                 self.channel_id, self.id)?;
+        if !self.content.is_empty() {
+            writeln!(f, "{}", self.content)?;
+        }
+        for attachment in &self.attachments {
+            writeln!(f, "Attachment: {}", attachment)?;
+        }
+        for embed in &self.embeds {
+            writeln!(f, "Embed: {}", embed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders the snapshots as a plain text file, oldest first.
+pub fn transcript(snapshots: &[MessageSnapshot]) -> String {
+    let mut sorted: Vec<&MessageSnapshot> = snapshots.iter().collect();
+    sorted.sort_by_key(|snapshot| snapshot.id);
+    sorted.iter().map(|snapshot| snapshot.to_string()).collect::<Vec<String>>().join("\n")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchivedMessage {
+    pub message: MessageSnapshot,
+    pub deleted_at: DateTime<Utc>,
+    /// Why bussy deleted it.
+    pub reason: String,
+}
+
+/// Messages bussy deleted in a guild, oldest first.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MessageArchive {
+    messages: VecDeque<ArchivedMessage>,
+}
+
+impl MessageArchive {
+    pub fn add(&mut self, snapshots: &[MessageSnapshot], reason: &str) {
+        let deleted_at = Utc::now();
+        self.messages.extend(snapshots.iter().map(|snapshot| ArchivedMessage { message: snapshot.clone(), deleted_at, reason: reason.to_string() }));
+    }
+
+    /// Drops messages deleted more than `retention_days` ago, then the oldest beyond `MAX_ARCHIVED_MESSAGES`.
+    pub fn prune(&mut self, retention_days: u32) {
+        let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
+        while self.messages.front().is_some_and(|archived| archived.deleted_at <= cutoff) {
+            self.messages.pop_front();
+        }
+        while self.messages.len() > MAX_ARCHIVED_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+}
+
+impl GuildShell {
+    /// Archives messages before they are deleted and returns their transcript for the log channel.
+    pub(crate) async fn archive_messages(&mut self, snapshots: &[MessageSnapshot], reason: &str) -> String {
+        if !snapshots.is_empty() {
+            self.archive.add(snapshots, reason);
+            self.archive.prune(*self.config.archive_retention_days);
+            let (guild_id, archive) = (self.config.guild_id, self.archive.clone());
+            if let Err(e) = blocking(&self.store, move |store| store.save_archive(guild_id, &archive)).await {
+                self.log_error("Saving the message archive failed", e);
+            }
+        }
+        transcript(snapshots)
+    }
+}
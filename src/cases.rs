@@ -0,0 +1,202 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::interactions::application_command::ApplicationCommandInteraction;
+use serenity::prelude::SerenityError;
+
+use crate::error_handling::Loggable;
+use crate::guild_shell::GuildShell;
+use crate::moderation::option;
+use crate::moderation_log::{truncate, LogEntry};
+use crate::storage::blocking;
+
+/// How many cases `/cases` lists at once.
+const CASES_PER_PAGE: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseAction {
+    Silence,
+    Unsilence,
+    Warn,
+}
+
+impl Display for CaseAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaseAction::Silence => "Silence",
+            CaseAction::Unsilence => "Unsilence",
+            CaseAction::Warn => "Warning",
+        })
+    }
+}
+
+/// Who took a moderation action.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Actor {
+    Bot,
+    Moderator(UserId),
+}
+
+impl Display for Actor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Actor::Bot => f.write_str("bussy"),
+            Actor::Moderator(id) => write!(f, "<@{}>", id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Case {
+    pub number: u32,
+    pub guild_id: GuildId,
+    pub target: UserId,
+    pub actor: Actor,
+    pub action: CaseAction,
+    pub reason: Option<String>,
+    /// The target's pressure when the case was opened.
+    pub pressure: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+    /// When a timed action like a silence ends.
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Case {
+    fn reason(&self, max_chars: usize) -> String {
+        let mut reason = self.reason.clone().unwrap_or_else(|| "no reason given".into());
+        truncate(&mut reason, max_chars);
+        reason
+    }
+
+    /// One line for case lists.
+    pub fn summary(&self) -> String {
+        format!("**#{}** `{}` {} <@{}> by {}: {}", self.number, self.timestamp.format("%Y-%m-%d %H:%M"), self.action, self.target,
+                self.actor, self.reason(100))
+    }
+
+    pub fn details(&self) -> String {
+        let mut lines = vec![
+            format!("**Action**: {}", self.action),
+            format!("**Member**: <@{}> ({})", self.target, self.target),
+            format!("**By**: {}", self.actor),
+            format!("**When**: <t:{}:f>", self.timestamp.timestamp()),
+            format!("**Reason**: {}", self.reason(3000)),
+        ];
+        if let Some(expires) = self.expires {
+            lines.push(format!("**Until**: <t:{}:f>", expires.timestamp()));
+        }
+        if let Some(pressure) = self.pressure {
+            lines.push(format!("**Pressure**: {:.0}", pressure));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Every moderation action taken in a guild, numbered from 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CaseLog {
+    cases: Vec<Case>,
+}
+
+impl CaseLog {
+    pub fn open(&mut self, guild_id: GuildId, target: UserId, actor: Actor, action: CaseAction, reason: Option<String>, pressure: Option<f64>) -> &Case {
+        let number = self.cases.last().map_or(1, |case| case.number + 1);
+        self.cases.push(Case { number, guild_id, target, actor, action, reason, pressure, timestamp: Utc::now(), expires: None });
+        self.cases.last().unwrap()
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Case> {
+        self.cases.iter().find(|case| case.number == number)
+    }
+
+    pub fn get_mut(&mut self, number: u32) -> Option<&mut Case> {
+        self.cases.iter_mut().find(|case| case.number == number)
+    }
+
+    /// Cases matching both filters, newest first.
+    pub fn search(&self, target: Option<UserId>, since: Option<DateTime<Utc>>) -> Vec<&Case> {
+        self.cases.iter().rev()
+            .filter(|case| target.is_none_or(|target| case.target == target))
+            .filter(|case| since.is_none_or(|since| case.timestamp >= since))
+            .collect()
+    }
+}
+
+/// Accepts `2024-05-01` or a full RFC 3339 timestamp.
+fn parse_date(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    DateTime::parse_from_rfc3339(text).map(|date| date.with_timezone(&Utc))
+        .map_err(|_| format!("{} is not a date like 2024-05-01", text))
+}
+
+impl GuildShell {
+    /// Opens a case and saves the guild's cases right away.
+    pub(crate) async fn open_case(&mut self, target: UserId, actor: Actor, action: CaseAction, reason: Option<String>, pressure: Option<f64>) -> u32 {
+        let number = self.cases.open(self.config.guild_id, target, actor, action, reason, pressure).number;
+        self.log(LogEntry::CaseOpened { number, action, target });
+        self.save_cases().await;
+        number
+    }
+
+    pub(crate) async fn save_cases(&mut self) {
+        let (guild_id, cases) = (self.config.guild_id, self.cases.clone());
+        if let Err(e) = blocking(&self.store, move |store| store.save_cases(guild_id, &cases)).await {
+            self.log_error("Saving the cases failed", e);
+        }
+    }
+
+    /// Handles `/case view`, `/case reason` and `/cases`.
+    pub(crate) async fn handle_case_command(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let (title, description) = match self.case_response(command).await {
+            Ok(response) => response,
+            Err(e) => ("Cases".to_string(), e)
+        };
+        command.create_interaction_response(&ctx, |resp| {
+            resp.interaction_response_data(|d| d.create_embed(|e| e.title(title).description(description)))
+        }).await
+    }
+
+    async fn case_response(&mut self, command: &ApplicationCommandInteraction) -> Result<(String, String), String> {
+        if command.data.name == "cases" {
+            let target = option(&command.data.options, "user").and_then(|v| v.as_str()).and_then(|id| id.parse::<u64>().ok()).map(UserId);
+            let since = option(&command.data.options, "since").and_then(|v| v.as_str()).map(parse_date).transpose()?;
+            let found = self.cases.search(target, since);
+            if found.is_empty() {
+                return Ok(("Cases".into(), "No cases found.".into()));
+            }
+            let lines: Vec<String> = found.iter().take(CASES_PER_PAGE).map(|case| case.summary()).collect();
+            let title = if found.len() > CASES_PER_PAGE {
+                format!("Cases ({} newest of {})", CASES_PER_PAGE, found.len())
+            } else {
+                format!("Cases ({})", found.len())
+            };
+            return Ok((title, lines.join("\n")));
+        }
+
+        let subcommand = command.data.options.first().ok_or("Missing subcommand")?;
+        let number = option(&subcommand.options, "number").and_then(|v| v.as_u64()).ok_or("Missing case number")? as u32;
+        match subcommand.name.as_str() {
+            "reason" => {
+                let reason = option(&subcommand.options, "reason").and_then(|v| v.as_str()).ok_or("Missing reason")?.to_string();
+                let case = self.cases.get_mut(number).ok_or(format!("There is no case #{}", number))?;
+                case.reason = Some(reason);
+                let details = case.details();
+                self.log(LogEntry::CaseUpdated { number, actor: command.user.id });
+                self.save_cases().await;
+                Ok((format!("Case #{} updated", number), details))
+            }
+            _ => {
+                let case = self.cases.get(number).ok_or(format!("There is no case #{}", number))?;
+                Ok((format!("Case #{}", number), case.details()))
+            }
+        }
+    }
+}
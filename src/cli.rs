@@ -0,0 +1,134 @@
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use serenity::client::bridge::gateway::GatewayIntents;
+use serenity::model::id::{ChannelId, GuildId};
+use tracing_subscriber::EnvFilter;
+
+use crate::BaseConfigData;
+
+/// Every flag can also be set through the environment variable named in its help, `.env` included.
+/// Flags can be given before or after the subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "bussy", version, about = "Keeps raids and spam out of Discord guilds")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub logging: LogArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Connect to Discord and start moderating. This is what runs without a subcommand
+    Run,
+    /// Overwrite the slash commands and exit without connecting to the gateway
+    RegisterCommands,
+    /// Load the stored guild configs, report any problems and exit
+    CheckData,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    #[arg(long, env = "BOT_TOKEN", hide_env_values = true, global = true)]
+    bot_token: Option<String>,
+
+    /// Usually the bot's user id
+    #[arg(long, env = "APPLICATION_ID", global = true)]
+    application_id: Option<u64>,
+
+    /// Channel for problems that need the bot owner's attention
+    #[arg(long, env = "DEBUG_CHANNEL_ID", global = true)]
+    debug_channel_id: Option<u64>,
+
+    /// Where guild configs are stored. `.db` and `.sqlite` files use SQLite, `:memory:` keeps nothing
+    #[arg(long, env = "BUSSY_DATA", default_value = "shells.yml", global = true)]
+    pub data: String,
+
+    /// Prefix of the text commands
+    #[arg(long, env = "BUSSY_PREFIX", default_value = "~", global = true)]
+    prefix: String,
+
+    /// `all`, `non-privileged` or the raw intents bitmask
+    #[arg(long, env = "BUSSY_INTENTS", default_value = "all", value_parser = parse_intents, global = true)]
+    intents: GatewayIntents,
+
+    /// Overwrite the slash commands on startup
+    #[arg(long, env = "BUSSY_REGISTER_COMMANDS", default_value_t = true, action = ArgAction::Set, global = true)]
+    register_commands: bool,
+
+    /// Register the slash commands in this guild only, where they update instantly. For development
+    #[arg(long, env = "BUSSY_DEV_GUILD", global = true)]
+    dev_guild: Option<u64>,
+
+    /// Days the config of a guild that removed bussy is kept, in case it is added back
+    #[arg(long, env = "BUSSY_REMOVED_GUILD_DAYS", default_value_t = 30, global = true)]
+    removed_guild_days: u32,
+}
+
+impl RunArgs {
+    /// Exits with a usage error if a value required to connect to Discord is missing.
+    pub fn into_base_config(self) -> BaseConfigData {
+        BaseConfigData {
+            bot_token: self.bot_token.unwrap_or_else(|| missing("--bot-token", "BOT_TOKEN")),
+            application_id: self.application_id.unwrap_or_else(|| missing("--application-id", "APPLICATION_ID")),
+            debug_channel_id: ChannelId(self.debug_channel_id.unwrap_or_else(|| missing("--debug-channel-id", "DEBUG_CHANNEL_ID"))),
+            shell_config_file: self.data,
+            command_prefix: self.prefix,
+            intents: self.intents,
+            register_commands: self.register_commands,
+            dev_guild: self.dev_guild.map(GuildId),
+            removed_guild_days: self.removed_guild_days,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct LogArgs {
+    /// Log filter like `info` or `warn,bussy=debug`. Message contents and interaction payloads are logged at `trace`
+    #[arg(long, env = "BUSSY_LOG", default_value = "warn,bussy=info", value_parser = parse_filter, global = true)]
+    log: String,
+
+    /// `json` writes one object per line with the fields of the surrounding shell and event spans
+    #[arg(long, env = "BUSSY_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+}
+
+impl LogArgs {
+    pub fn init(&self) {
+        let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&self.log));
+        match self.log_format {
+            LogFormat::Text => builder.init(),
+            LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+        }
+    }
+}
+
+fn missing(flag: &str, variable: &str) -> ! {
+    Cli::command()
+        .error(ErrorKind::MissingRequiredArgument, format!("{} or the {} environment variable is required", flag, variable))
+        .exit()
+}
+
+fn parse_filter(value: &str) -> Result<String, String> {
+    EnvFilter::try_new(value).map(|_| value.to_string()).map_err(|e| e.to_string())
+}
+
+fn parse_intents(value: &str) -> Result<GatewayIntents, String> {
+    match value {
+        "all" => Ok(GatewayIntents::all()),
+        "non-privileged" => Ok(GatewayIntents::non_privileged()),
+        bits => bits.parse::<u64>()
+            .map(GatewayIntents::from_bits_truncate)
+            .map_err(|_| format!("{} is not `all`, `non-privileged` or an intents bitmask", bits))
+    }
+}
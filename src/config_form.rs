@@ -1,15 +1,27 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Utc;
+use serde_json::Value;
 use serenity::builder::{CreateApplicationCommand, CreateComponents, CreateSelectMenuOptions};
 use serenity::client::Context;
+use serenity::http::AttachmentType;
 use serenity::model::channel::{ChannelType, GuildChannel};
 use serenity::model::guild::Role;
-use serenity::model::id::{ChannelId, RoleId};
+use serenity::model::id::{ChannelId, RoleId, UserId};
 use serenity::model::interactions::Interaction;
 use serenity::model::interactions::message_component::ButtonStyle;
 use serenity::model::prelude::application_command::ApplicationCommandOptionType;
 use serenity::prelude::SerenityError;
 
-use crate::error_handling::BetterHandle;
-use crate::guild_shell::{ConfigField, GuildShell};
+use tokio::time::Instant;
+use tracing::{debug, trace, warn};
+
+use crate::config_history::ConfigChange;
+use crate::error_handling::{BetterHandle, Loggable};
+use crate::guild_shell::{ConfigField, GuildConfig, GuildShell};
+use crate::moderation_log::{batch_embeds, LogEmbed, LogEntry};
+use crate::presets::ConfigPreset;
+use crate::user_records::WHOIS_USER_COMMAND;
 
 pub trait Configurable {
     fn get_name(&self) -> &String;
@@ -20,30 +32,26 @@ pub trait Configurable {
     fn get_setting_key(&self) -> String {
         format!("set_{}", self.get_name())
     }
-    fn get_reset_key(&self) -> String {
-        format!("set_{}", self.get_name())
-    }
 
     fn set_value(&mut self, new_value: String) -> Result<(), String>;
 
+    /// Human readable representation of the current value, used for the change history.
+    fn get_value(&self) -> String;
+
+    /// Checks that the current value makes sense for the guild it is configured for.
+    fn validate(&self, _roles: &[&Role], _channels: &[&GuildChannel]) -> Result<(), String> {
+        Ok(())
+    }
+
     fn add_selection_option(&self, options: &mut CreateSelectMenuOptions) {
         options.create_option(|op| {
             op
-                .label(format!("{}", self.get_pretty_name()))
+                .label(self.get_pretty_name())
                 .description(format!("Set the value for {}", self.get_pretty_name()))
                 .value(self.get_selection_key())
         });
     }
 
-    fn setting_updated(&mut self, key: String, value: String) -> Result<bool, String> {
-        if self.get_setting_key() == key {
-            self.set_value(value)?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
-    }
-
     fn make_config_window(&self, components: &mut CreateComponents, _roles: Vec<&Role>, _channels: Vec<&GuildChannel>) {
         components.create_action_row(|row| {
             row.create_button(|button| {
@@ -96,6 +104,18 @@ impl Configurable for ConfigField<f64> {
         }
     }
 
+    fn get_value(&self) -> String {
+        self._inner.to_string()
+    }
+
+    fn validate(&self, _roles: &[&Role], _channels: &[&GuildChannel]) -> Result<(), String> {
+        if self._inner.is_finite() && self._inner >= 0. {
+            Ok(())
+        } else {
+            Err(format!("{} has to be a positive number", self._inner))
+        }
+    }
+
     fn get_slash_command_type(&self) -> ApplicationCommandOptionType {
         ApplicationCommandOptionType::Number
     }
@@ -111,7 +131,7 @@ impl Configurable for ConfigField<Option<ChannelId>> {
     }
 
     fn set_value(&mut self, new_value: String) -> Result<(), String> {
-        if new_value == "" {
+        if new_value.is_empty() {
             self._inner = None;
             return Ok(());
         }
@@ -120,12 +140,26 @@ impl Configurable for ConfigField<Option<ChannelId>> {
             Ok(to_num) => {
                 let channel_id = ChannelId::from(to_num);
                 self._inner = Some(channel_id);
-                return Ok(());
+                Ok(())
             }
             Err(e) => Err(format!("{} is not a valid id: {}", new_value, e))
         }
     }
 
+    fn get_value(&self) -> String {
+        match self._inner {
+            Some(id) => format!("<#{}>", id),
+            None => "none".into()
+        }
+    }
+
+    fn validate(&self, _roles: &[&Role], channels: &[&GuildChannel]) -> Result<(), String> {
+        match self._inner {
+            Some(id) if !channels.iter().any(|c| c.id == id) => Err(format!("channel {} does not exist in this server", id)),
+            _ => Ok(())
+        }
+    }
+
 
     fn make_config_window(&self, components: &mut CreateComponents, _roles: Vec<&Role>, channels: Vec<&GuildChannel>) {
         /*components.create_action_row(|row| {
@@ -163,7 +197,7 @@ impl Configurable for ConfigField<Option<RoleId>> {
     }
 
     fn set_value(&mut self, new_value: String) -> Result<(), String> {
-        if new_value == "" {
+        if new_value.is_empty() {
             self._inner = None;
             return Ok(());
         }
@@ -173,13 +207,79 @@ impl Configurable for ConfigField<Option<RoleId>> {
             self._inner = Some(role_id);
             return Ok(());
         }
-        return Err("Not a valid id".into());
+        Err("Not a valid id".into())
+    }
+
+    fn get_value(&self) -> String {
+        match self._inner {
+            Some(id) => format!("<@&{}>", id),
+            None => "none".into()
+        }
+    }
+
+    fn validate(&self, roles: &[&Role], _channels: &[&GuildChannel]) -> Result<(), String> {
+        match self._inner {
+            Some(id) if !roles.iter().any(|r| r.id == id) => Err(format!("role {} does not exist in this server", id)),
+            _ => Ok(())
+        }
+    }
+
+    fn make_config_window(&self, components: &mut CreateComponents, roles: Vec<&Role>, _channels: Vec<&GuildChannel>) {
+        components.create_action_row(|row| {
+            row.create_select_menu(|menu| {
+                menu.custom_id(self.get_setting_key())
+                    .options(|op| {
+                        for r in roles {
+                            op.create_option(|o| {
+                                o.label(&r.name).description(format!("Choose {}", &r.name)).value(r.id)
+                            });
+                        }
+                        op
+                    })
+            })
+        });
+    }
+}
+
+impl Configurable for ConfigField<Vec<RoleId>> {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn set_value(&mut self, new_value: String) -> Result<(), String> {
+        let mut roles = Vec::new();
+        for part in new_value.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+            let id = part.trim_start_matches("<@&").trim_end_matches('>');
+            match id.parse::<u64>() {
+                Ok(to_num) => roles.push(RoleId::from(to_num)),
+                Err(e) => return Err(format!("{} is not a valid role: {}", part, e))
+            }
+        }
+        self._inner = roles;
+        Ok(())
+    }
+
+    fn get_value(&self) -> String {
+        if self._inner.is_empty() {
+            return "none".into();
+        }
+        self._inner.iter().map(|id| format!("<@&{}>", id)).collect::<Vec<String>>().join(", ")
+    }
+
+    fn validate(&self, roles: &[&Role], _channels: &[&GuildChannel]) -> Result<(), String> {
+        match self._inner.iter().find(|id| !roles.iter().any(|r| &r.id == *id)) {
+            Some(id) => Err(format!("role {} does not exist in this server", id)),
+            None => Ok(())
+        }
     }
 
     fn make_config_window(&self, components: &mut CreateComponents, roles: Vec<&Role>, _channels: Vec<&GuildChannel>) {
+        let max_values = roles.len().clamp(1, 25) as u64;
         components.create_action_row(|row| {
             row.create_select_menu(|menu| {
                 menu.custom_id(self.get_setting_key())
+                    .min_values(0)
+                    .max_values(max_values)
                     .options(|op| {
                         for r in roles {
                             op.create_option(|o| {
@@ -198,8 +298,18 @@ impl Configurable for ConfigField<u32> {
         &self.name
     }
 
-    fn set_value(&mut self, _new_value: String) -> Result<(), String> {
-        todo!()
+    fn set_value(&mut self, new_value: String) -> Result<(), String> {
+        match new_value.parse() {
+            Ok(val) => {
+                self._inner = val;
+                Ok(())
+            }
+            Err(e) => Err(format!("{} is not a valid whole number: {}", new_value, e))
+        }
+    }
+
+    fn get_value(&self) -> String {
+        self._inner.to_string()
     }
 
     fn get_slash_command_type(&self) -> ApplicationCommandOptionType {
@@ -223,45 +333,152 @@ impl GuildShell {
         });
     }
 
-    pub async fn dump_logs(&mut self, ctx: &Context) -> Result<(), SerenityError> {
-        let res = self._dump_logs(ctx).await;
-        match &res {
-            Ok(()) => { println!("Logs dumped successfully") }
-            Err(e) => { println!("Dumping logs failed: {}", e) }
+    /// Sets the field called `name` and records who changed it in the config history.
+    fn change_setting(&mut self, actor: UserId, name: &str, value: String) -> Result<(), String> {
+        let (old_value, new_value) = {
+            let field = self.config.get_configurable_fields().into_iter().find(|f| f.get_name() == name)
+                .ok_or_else(|| format!("{} is not a known setting", name))?;
+            let old_value = field.get_value();
+            field.set_value(value)?;
+            (old_value, field.get_value())
+        };
+        if old_value == new_value {
+            return Ok(());
+        }
+
+        let change = ConfigChange { actor, timestamp: Utc::now(), field: name.to_string(), old_value, new_value };
+        self.log(LogEntry::ConfigChanged(change.clone()));
+        self.config.history.record(change);
+        self.share_admin_roles();
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Sets every field covered by the preset and remembers it as the guild's preset.
+    fn apply_preset(&mut self, actor: UserId, name: &str, preset: &ConfigPreset) -> Result<(), String> {
+        for (field, value) in preset.values() {
+            self.change_setting(actor, field, value)?;
         }
-        res
+        self.config.preset = Some(name.to_string());
+        self.log(LogEntry::PresetApplied { actor, preset: name.to_string() });
+        Ok(())
     }
 
-    async fn _dump_logs(&mut self, ctx: &Context) -> Result<(), SerenityError> {
-        if let Some(ch) = *self.config.log_channel {
-            if let Some(serverlog) = self._log.dump() {
-                ch.send_message(&ctx, |msg| {
+    /// Lists all current values, marking the ones that differ from the guild's preset.
+    fn describe_config(&mut self, presets: &BTreeMap<String, ConfigPreset>) -> String {
+        let preset_name = self.config.preset.clone().unwrap_or_else(|| "balanced".into());
+        let mut lines = Vec::new();
+        let preset_values: HashMap<&str, String> = match presets.get(&preset_name) {
+            Some(preset) => {
+                lines.push(format!("Values that differ from the **{}** preset are marked.\n", preset_name));
+                preset.values().into_iter().collect()
+            }
+            None => {
+                lines.push(format!("The **{}** preset no longer exists.\n", preset_name));
+                HashMap::new()
+            }
+        };
+
+        for field in self.config.get_configurable_fields() {
+            let value = field.get_value();
+            match preset_values.get(field.get_name().as_str()) {
+                Some(preset_value) if preset_value != &value => lines.push(format!("**{}**: {} *(preset: {})*", field.get_pretty_name(), value, preset_value)),
+                _ => lines.push(format!("**{}**: {}", field.get_pretty_name(), value))
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Replaces the whole config with a loaded one, keeping the guild id and the change history.
+    pub(crate) fn load_config(&mut self, actor: UserId, mut config: GuildConfig) {
+        config.guild_id = self.config.guild_id;
+        config.load_names();
+        config.history = std::mem::take(&mut self.config.history);
+
+        for (field, old_value, new_value) in self.config.diff(&mut config) {
+            let change = ConfigChange { actor, timestamp: Utc::now(), field, old_value, new_value };
+            self.log(LogEntry::ConfigChanged(change.clone()));
+            config.history.record(change);
+        }
+        self.config = config;
+        self.share_admin_roles();
+        self.mark_dirty();
+    }
+
+    /// Posts and clears everything logged since the last flush. Entries are dropped if there is no
+    /// log channel, they were already written to the bot's own log when they were added.
+    pub async fn dump_logs(&mut self) {
+        self.last_log_flush = Instant::now();
+        let embeds = self.log_embeds();
+        let channel = match *self.config.log_channel {
+            Some(channel) => channel,
+            None => return
+        };
+
+        for message in batch_embeds(embeds) {
+            let res = channel.send_message(&self.http, |msg| {
+                for attachment in message.iter().flat_map(|embed| &embed.attachments) {
+                    msg.add_file(AttachmentType::Bytes { data: attachment.content.as_bytes().to_vec().into(), filename: attachment.filename.clone() });
+                }
+                for embed in &message {
                     msg.add_embed(|e| {
-                        e.title("Server info").description(format!("```{}```", serverlog))
-                    })
-                }).await?;   //.dexpect("Failed to send server log  to the log channel", &mut self._log);
-                self._log.clear();
+                        e.title(&embed.title).description(&embed.description).color(embed.color);
+                        if let Some(url) = &embed.thumbnail {
+                            e.thumbnail(url);
+                        }
+                        for (name, value) in &embed.fields {
+                            e.field(name, value, true);
+                        }
+                        e
+                    });
+                }
+                msg
+            }).await;
+            match res {
+                Ok(_) => debug!("Logs dumped"),
+                Err(e) => warn!("Dumping logs failed: {}", e)
             }
+        }
+    }
 
-            for m in self.active_members.values_mut() {
-                if let Some(memberlogs) = m._log.dump() {
-                    ch.send_message(&ctx, |msg| {
-                        msg.add_embed(|e| {
-                            e.title(format!("Member {} was silenced!", m.member.nick.as_ref().unwrap_or(&m.member.user.name))).description(format!("<@{}>\n```{}```", m.member.user.id, memberlogs))
-                        })
-                    }).await?;   // .dexpect("Failed to send message to the log channel", &mut self._log);
-                    m._log.clear();
+    /// Takes the pending server and member logs as embeds, with member details on the first embed
+    /// of each member.
+    fn log_embeds(&mut self) -> Vec<LogEmbed> {
+        let mut embeds = Vec::new();
+        let color = self._log.color();
+        for description in self._log.pages() {
+            embeds.push(LogEmbed { title: "Server log".into(), description, color, thumbnail: None, fields: Vec::new(), attachments: Vec::new() });
+        }
+        if let Some(first) = embeds.first_mut() {
+            first.attachments = self._log.take_attachments();
+        }
+        self._log.clear();
+
+        for m in self.active_members.values_mut() {
+            let color = m._log.color();
+            let user = &m.member.user;
+            for (page, description) in m._log.pages().into_iter().enumerate() {
+                let mut embed = LogEmbed { title: m.member.display_name().to_string(), description, color, thumbnail: None, fields: Vec::new(), attachments: Vec::new() };
+                if page == 0 {
+                    embed.attachments = m._log.take_attachments();
+                    let joined = m.member.joined_at.map_or("unknown".to_string(), |joined| format!("<t:{}:R>", joined.timestamp()));
+                    embed.thumbnail = Some(user.face());
+                    embed.fields = vec![
+                        ("Member".into(), format!("<@{}>", user.id)),
+                        ("Id".into(), user.id.to_string()),
+                        ("Joined".into(), joined),
+                        ("Account created".into(), format!("<t:{}:R>", user.created_at().timestamp())),
+                    ];
                 }
+                embeds.push(embed);
             }
-            Ok(())
-        } else {
-            println!("Coudlnt dump logs");
-            Err(SerenityError::Other("No log channel configured"))
+            m._log.clear();
         }
+        embeds
     }
 
     pub async fn handle_interaction(&mut self, ctx: &Context, interaction: &Interaction) -> Result<(), SerenityError> {
-        println!("Interaction handling: {}", serde_yaml::to_string(&interaction).unwrap());
+        trace!(payload = %serde_json::to_string(interaction).unwrap_or_default(), "Handling interaction");
 
         match interaction {
             Interaction::ApplicationCommand(c) => {
@@ -275,56 +492,113 @@ impl GuildShell {
                     return Ok(());
                 }
             }
-            _ => println!("Unknown interaction type?? {}", serde_yaml::to_string(&interaction.kind()).unwrap())
+            _ => debug!(kind = ?interaction.kind(), "Ignoring interaction")
         }
 
         match interaction {
             Interaction::ApplicationCommand(command) => {
                 match command.data.name.as_ref() {
                     "config" => {
-                        command.create_interaction_response(&ctx, |resp| {
-                            resp.interaction_response_data(|d| {
-                                d.create_embed(|e|
-                                    e
-                                        .title("Bussy configuration")
-                                        .description("Pick which setting do you want to configure. That will bring you onto the next screen."))
-                                    .components(|c| {
-                                        self.add_selection_components(c);
-                                        c
+                        match command.data.options.first().map(|o| o.name.as_str()) {
+                            Some("view") => {
+                                let presets = self.presets.clone();
+                                let description = self.describe_config(&presets);
+                                command.create_interaction_response(&ctx, |resp| {
+                                    resp.interaction_response_data(|d| {
+                                        d.create_embed(|e| e.title("Current configuration").description(description))
                                     })
-                            })
-                        }).await.dexpect("Failed to send interaction response", &mut self._log);
+                                }).await?;
+                            }
+                            Some("preset") => {
+                                let name = command.data.options[0].options.first()
+                                    .and_then(|o| o.value.as_ref()).and_then(|v| v.as_str()).unwrap_or("").to_string();
+                                let preset = self.presets.get(&name).cloned();
+                                let body = match preset {
+                                    Some(preset) => match self.apply_preset(command.user.id, &name, &preset) {
+                                        Ok(()) => format!("Applied the {} preset. Check the result with `/config view`.", name),
+                                        Err(e) => format!("Couldn't apply the {} preset: {}", name, e)
+                                    },
+                                    None => format!("There is no preset called {}", name)
+                                };
+                                command.create_interaction_response(&ctx, |resp| {
+                                    resp.interaction_response_data(|d| {
+                                        d.create_embed(|e| e.title("Preset").description(body))
+                                    })
+                                }).await?;
+                            }
+                            Some("history") => {
+                                let history = self.config.history.dump(15).unwrap_or_else(|| "No configuration changes recorded yet.".into());
+                                command.create_interaction_response(&ctx, |resp| {
+                                    resp.interaction_response_data(|d| {
+                                        d.create_embed(|e| e.title("Configuration history").description(history))
+                                    })
+                                }).await?;
+                            }
+                            _ => {
+                                command.create_interaction_response(&ctx, |resp| {
+                                    resp.interaction_response_data(|d| {
+                                        d.create_embed(|e|
+                                            e
+                                                .title("Bussy configuration")
+                                                .description("Pick which setting do you want to configure. That will bring you onto the next screen."))
+                                            .components(|c| {
+                                                self.add_selection_components(c);
+                                                c
+                                            })
+                                    })
+                                }).await.dexpect("Failed to send interaction response", &mut self._log);
+                            }
+                        }
                     }
                     "change" => {
-                        let fields = self.config.get_configurable_fields();
-                        let name = &command.data.options[0].name;
-                        let value = &command.data.options[0].options[0].value;
-
-
-                        if let Some(field) = fields.into_iter().find(|f| f.get_name() == name) {
-                            let res = match value {
-                                Some(s) => field.set_value(s.as_str().unwrap_or("").to_string()),
-                                None => field.set_value("".to_string())
-                            };
-
-                            let title = match res {
-                                Ok(()) => format!("Change successful"),
-                                Err(_) => format!("No.")
-                            };
-                            let body = match res {
-                                Ok(()) => format!("{} changed successfully", name),
-                                Err(e) => format!("{}", e)
-                            };
-
-                            command.create_interaction_response(&ctx, |resp| {
-                                resp.interaction_response_data(|data| {
-                                    data.create_embed(|e| {
-                                        e.title(title)
-                                            .description(body)
-                                    })
+                        let option = &command.data.options[0];
+                        let name = option.name.clone();
+                        let value = match option.options.first().and_then(|o| o.value.as_ref()) {
+                            Some(Value::String(s)) => s.clone(),
+                            Some(v) => v.to_string(),
+                            None => "".to_string()
+                        };
+
+                        let res = self.change_setting(command.user.id, &name, value);
+
+                        let title = match res {
+                            Ok(()) => "Change successful".to_string(),
+                            Err(_) => "No.".to_string()
+                        };
+                        let body = match res {
+                            Ok(()) => format!("{} changed successfully", name),
+                            Err(e) => e.to_string()
+                        };
+
+                        command.create_interaction_response(&ctx, |resp| {
+                            resp.interaction_response_data(|data| {
+                                data.create_embed(|e| {
+                                    e.title(title)
+                                        .description(body)
                                 })
-                            }).await?;
-                        }
+                            })
+                        }).await?;
+                    }
+                    "case" | "cases" => {
+                        self.handle_case_command(ctx, command).await?;
+                    }
+                    "whois" | WHOIS_USER_COMMAND => {
+                        self.handle_whois(ctx, command).await?;
+                    }
+                    "warn" => {
+                        self.handle_warn(ctx, command).await?;
+                    }
+                    "note" => {
+                        self.handle_note(ctx, command).await?;
+                    }
+                    "silence" => {
+                        self.handle_silence(ctx, command).await?;
+                    }
+                    "unsilence" => {
+                        self.handle_unsilence(ctx, command).await?;
+                    }
+                    "purge" => {
+                        self.handle_purge(ctx, command).await?;
                     }
                     "setup" => {
                         let helptext = self.config.setup_help();
@@ -334,9 +608,9 @@ impl GuildShell {
                                     e.title("Setup wizard!").description(helptext)
                                 })
                             })
-                        }).await;
+                        }).await?;
                     }
-                    _ => println!("Unknown application command {}!", command.data.name)
+                    _ => debug!(command = %command.data.name, "Not a shell command")
                 }
             }
             Interaction::MessageComponent(component) => {
@@ -370,10 +644,9 @@ impl GuildShell {
                             break;
                         }
                     }
-                } else if custom_id.starts_with("set_") {
-                    let fl = self.config.get_configurable_fields().into_iter().find(|field| &field.get_setting_key() == custom_id);
-                    if let Some(f) = fl {
-                        let res = f.set_value(component.data.values[0].clone());
+                } else if let Some(name) = custom_id.strip_prefix("set_") {
+                    if self.config.get_configurable_fields().iter().any(|field| field.get_name() == name) {
+                        let res = self.change_setting(component.user.id, name, component.data.values.join(","));
                         let to_say = match res {
                             Ok(()) => "Value changed successfully!".to_string(),
                             Err(e) => format!("Couldn't set value: {}", e)
@@ -390,7 +663,7 @@ impl GuildShell {
                             })
                         }).await?;
                     } else {
-                        println!("Nonexisting field");
+                        warn!(field = name, "Component for a field that doesn't exist");
                     }
                 }
             }
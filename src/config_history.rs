@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::UserId;
+
+/// How many config changes are kept per guild before the oldest ones are dropped.
+const MAX_HISTORY_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigChange {
+    pub actor: UserId,
+    pub timestamp: DateTime<Utc>,
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl Display for ConfigChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` <@{}> changed **{}**: {} → {}",
+               self.timestamp.format("%Y-%m-%d %H:%M UTC"), self.actor, self.field, self.old_value, self.new_value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConfigHistory {
+    changes: VecDeque<ConfigChange>,
+}
+
+impl ConfigHistory {
+    pub fn record(&mut self, change: ConfigChange) {
+        self.changes.push_back(change);
+        while self.changes.len() > MAX_HISTORY_LENGTH {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Renders the `count` most recent changes, newest first.
+    pub fn dump(&self, count: usize) -> Option<String> {
+        if self.changes.is_empty() {
+            return None;
+        }
+        let lines: Vec<String> = self.changes.iter().rev().take(count).map(|c| c.to_string()).collect();
+        Some(lines.join("\n"))
+    }
+}
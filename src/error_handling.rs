@@ -1,16 +1,18 @@
-use crate::guild_shell::LogData;
+use std::fmt::Display;
+
+
+use crate::moderation_log::{LogData, LogEntry};
 
 pub(crate) trait Loggable {
-    fn slog(&mut self, content: String);
-    fn log(&mut self, content: &str) {
-        self.slog(content.to_string());
+    fn log(&mut self, entry: LogEntry);
+    fn log_error(&mut self, context: &str, error: impl Display) {
+        self.log(LogEntry::Error { message: format!("{}: {}", context, error) });
     }
 }
 
 
 pub trait BetterHandle<T> {
     fn dexpect(self, msg: &str, log: &mut LogData) -> T;
-    fn dexpect_or_default(self, msg: &str, log: &mut LogData, default: T) -> T;
 }
 
 impl<T> BetterHandle<T> for Option<T> {
@@ -18,22 +20,11 @@ impl<T> BetterHandle<T> for Option<T> {
         match self {
             Some(val) => val,
             None => {
-                log.log(&msg);
+                log.log(LogEntry::Error { message: msg.to_string() });
                 panic!("Expect failed: {}", &msg)
             }
         }
     }
-
-    fn dexpect_or_default(self, msg: &str, log: &mut LogData, default: T) -> T {
-        match self {
-            Some(val) => val,
-            None => {
-                log.log(&msg);
-                println!("{}", msg);
-                default
-            }
-        }
-    }
 }
 
 impl<T, E> BetterHandle<T> for Result<T, E>
@@ -43,21 +34,9 @@ impl<T, E> BetterHandle<T> for Result<T, E>
         match self {
             Ok(val) => val,
             Err(e) => {
-                log.log(msg);
+                log.log(LogEntry::Error { message: msg.to_string() });
                 panic!("Expect failed because of {}: {}", e, &msg)
             }
         }
     }
-
-    fn dexpect_or_default(self, msg: &str, log: &mut LogData, default: T) -> T {
-        match self {
-            Ok(val) => val,
-            Err(e) => {
-                log.slog(format!("{}: {}", &msg, e));
-                println!("{}: {}", &msg, e);
-                default
-            }
-        }
-    }
 }
-
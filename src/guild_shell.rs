@@ -1,5 +1,5 @@
-use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
 
 
 
@@ -7,61 +7,94 @@ use chrono::prelude::*;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::client::Context;
 use serenity::Error;
+use serenity::http::Http;
 use serenity::futures::task::AtomicWaker;
-use serenity::model::channel::Message;
-use serenity::model::guild::Member;
+use serenity::model::channel::{GuildChannel, Message};
+use serenity::model::guild::{Member, Role};
+use serenity::model::user::User;
 
 use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
-use serenity::prelude::{SerenityError, TypeMapKey};
+use serenity::prelude::SerenityError;
 use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info, info_span, warn, Instrument};
 
 
 
 use crate::{GuildShells, ShellContact, ShellEvent};
+use crate::archive::{MessageArchive, MessageSnapshot};
+use crate::cases::{Actor, CaseAction, CaseLog};
+use crate::message_cache::MessageCache;
+use crate::user_records::{Departure, UserRecords};
 use crate::config_form::Configurable;
+use crate::config_history::{ConfigChange, ConfigHistory};
+use crate::presets::{ConfigPreset, ConfigPresets};
+use crate::schema::CURRENT_SCHEMA_VERSION;
+use crate::storage::{blocking, ShellStorage, ShellStore};
 use crate::error_handling::*;
+use crate::moderation_log::{LogData, LogEntry};
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RaidInfo {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RaidInfo {
     raid_started: DateTime<Utc>,
     raiders: Vec<UserId>,
 }
 
-//pub type LogData = HashMap<DateTime<Utc>, String>;
-#[derive(Debug)]
-pub struct LogData {
-    records: BTreeMap<DateTime<Utc>, String>,
+/// Pressure of a member, kept across restarts so spammers can't reset it by waiting for one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemberPressure {
+    pressure: f64,
+    last_pressure_decay: DateTime<Utc>,
+    recent_messages: Vec<MessageLocation>,
 }
 
-impl LogData {
-    pub fn dump(&self) -> Option<String> {
-        if self.records.len() == 0 {
-            return None;
-        }
-        let mut res = String::new();
-        for log in self.records.iter() {
-            res = format!("{}{}: {}\n", res, log.0.format("%H:%M:%S UTC"), log.1);
-        }
-        Some(res)
-    }
-
-    pub fn clear(&mut self) {
-        self.records.clear();
-    }
+/// Moderation state of a guild that is persisted separately from its config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RuntimeState {
+    current_raid: Option<RaidInfo>,
+    last_raid: Option<RaidInfo>,
+    member_pressure: HashMap<UserId, MemberPressure>,
+    // Members whose silencing started but did not finish
+    pending_silences: Vec<UserId>,
+    // When timed silences end
+    silence_expiries: HashMap<UserId, DateTime<Utc>>,
 }
 
-impl Default for LogData {
-    fn default() -> Self {
-        LogData { records: Default::default() }
+pub(crate) type MessageLocation = (MessageId, ChannelId);
+
+/// How long a changed config waits before it is saved, so bursts of changes are written once.
+const SAVE_DELAY: Duration = Duration::from_secs(3);
+/// Same for the runtime state, which changes with nearly every message.
+const STATE_SAVE_DELAY: Duration = Duration::from_secs(30);
+/// Log entries are posted to the log channel in batches at most this often.
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// Discord deletes at most this many messages per bulk delete request.
+const BULK_DELETE_LIMIT: usize = 100;
+/// Timed silences that could not be lifted are tried again after this many minutes.
+const SILENCE_LIFT_RETRY_MINUTES: i64 = 10;
+
+/// Deletes the messages in as few bulk deletes per channel as possible. Returns how many were deleted
+/// and the last error, if any request failed. Bulk deletes only work on messages younger than two weeks.
+pub(crate) async fn delete_messages(http: &Http, messages: &[MessageLocation]) -> (usize, Option<SerenityError>) {
+    let mut channel_map: HashMap<ChannelId, Vec<MessageId>> = HashMap::default();
+    for (message, channel) in messages {
+        channel_map.entry(*channel).or_default().push(*message);
     }
-}
 
-impl TypeMapKey for LogData {
-    type Value = LogData;
+    let mut deleted = 0;
+    let mut failed = None;
+    for (channel, messages) in channel_map {
+        for batch in messages.chunks(BULK_DELETE_LIMIT) {
+            match channel.delete_messages(http, batch).await {
+                Ok(()) => deleted += batch.len(),
+                Err(e) => failed = Some(e)
+            }
+        }
+    }
+    (deleted, failed)
 }
 
-type MessageLocation = (MessageId, ChannelId);
-
 
 #[derive(Debug)]
 pub(crate) struct MemberShell {
@@ -75,12 +108,36 @@ pub(crate) struct MemberShell {
 
 impl From<Member> for MemberShell {
     fn from(member: Member) -> Self {
-        let shell = MemberShell { member, current_pressure: 0., _log: Default::default(), last_pressure_decay: Utc::now(), recent_messages: Default::default(), cleanup_in_progress: false };
-        shell
+        MemberShell { member, current_pressure: 0., _log: Default::default(), last_pressure_decay: Utc::now(), recent_messages: Default::default(), cleanup_in_progress: false }
     }
 }
 
 impl MemberShell {
+    fn restore_pressure(&mut self, saved: MemberPressure) {
+        self.current_pressure = saved.pressure;
+        self.last_pressure_decay = saved.last_pressure_decay;
+        self.recent_messages = saved.recent_messages;
+    }
+
+    /// The pressure to persist, or None if it will have decayed completely by now.
+    fn saved_pressure(&self, decay_per_second: f64) -> Option<MemberPressure> {
+        let decayed = (Utc::now() - self.last_pressure_decay).num_seconds() as f64 * decay_per_second;
+        if self.current_pressure - decayed <= 0. {
+            return None;
+        }
+        Some(MemberPressure {
+            pressure: self.current_pressure,
+            last_pressure_decay: self.last_pressure_decay,
+            recent_messages: self.recent_messages.clone(),
+        })
+    }
+
+    /// The pressure with the decay since the last message applied.
+    fn current_pressure(&self, decay_per_second: f64) -> f64 {
+        let decayed = (Utc::now() - self.last_pressure_decay).num_seconds() as f64 * decay_per_second;
+        (self.current_pressure - decayed).max(0.)
+    }
+
     fn update_pressure(&mut self, decay_per_second: &f64, add_pressure: &f64) -> f64 {
         let current_time = Utc::now();
         let to_decay: f64 = (current_time - self.last_pressure_decay).num_seconds() as f64 * decay_per_second;
@@ -106,7 +163,7 @@ impl<T> std::ops::Deref for ConfigField<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        return &self._inner;
+        &self._inner
     }
 }
 
@@ -126,6 +183,12 @@ impl<T> Serialize for ConfigField<T> where T: Serialize {
     }
 }
 
+impl<T> Default for ConfigField<T> where T: Default {
+    fn default() -> Self {
+        T::default().into()
+    }
+}
+
 impl<'a, T> Deserialize<'a> for ConfigField<T> where T: Deserialize<'a> {
     fn deserialize<'de, D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'a> {
         let val = T::deserialize(deserializer)?;
@@ -137,16 +200,20 @@ impl<'a, T> Deserialize<'a> for ConfigField<T> where T: Deserialize<'a> {
 
 
 #[derive(Serialize, Deserialize, Debug, Clone)]  // Serializing and deserializing channels will probably have to be reduced to their IDs, not the whole structs
+#[serde(default)]  // Missing fields keep their defaults, see crate::schema for anything that needs more than that
 pub struct GuildConfig {
+    schema_version: u32,
     pub guild_id: GuildId,
     moderation_channel: ConfigField<Option<ChannelId>>,
     raid_containment_channel: ConfigField<Option<ChannelId>>,
-    silence_containment_channel: ConfigField<Option<ChannelId>>,
+    pub(crate) silence_containment_channel: ConfigField<Option<ChannelId>>,
     pub(crate) log_channel: ConfigField<Option<ChannelId>>,
 
     member_role: ConfigField<Option<RoleId>>,
     silence_role: ConfigField<Option<RoleId>>,
     new_role: ConfigField<Option<RoleId>>,
+    // Members with any of these roles can use configuration commands, on top of anyone with Manage Guild
+    admin_roles: ConfigField<Vec<RoleId>>,
 
     raid_trigger_timespan: ConfigField<u32>,
     // RConfigField<aid> is triggered if n users join within this timespan, in seconds
@@ -155,18 +222,38 @@ pub struct GuildConfig {
 
     // Antispam pressure section
     max_pressure: ConfigField<f64>,
-    message_pressure: ConfigField<f64>,
+    pub(crate) message_pressure: ConfigField<f64>,
     embed_pressure: ConfigField<f64>,
     character_pressure: ConfigField<f64>,
     newline_pressure: ConfigField<f64>,
     unique_ping_pressure: ConfigField<f64>,
     pressure_decay_per_second: ConfigField<f64>,
     // consider adding custom regex filters for pressure, as well as extra pressure for repeated messages
+
+    // Members are silenced once moderators have warned them this many times, 0 turns it off
+    pub(crate) warning_limit: ConfigField<u32>,
+    // Deleted messages are archived for this many days
+    pub(crate) archive_retention_days: ConfigField<u32>,
+
+    pub(crate) history: ConfigHistory,
+    // Name of the preset applied last, fields are compared against it in /config view
+    pub(crate) preset: Option<String>,
+    // Set when bussy was removed from the guild, the config is deleted once it is old enough
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) removed_at: Option<DateTime<Utc>>,
+}
+
+impl Default for GuildConfig {
+    fn default() -> Self {
+        GuildConfig::new(GuildId(0))
+    }
 }
 
 impl GuildConfig {
     pub(crate) fn new(guild_id: GuildId) -> Self {
+        let defaults = ConfigPreset::balanced();
         let mut new = GuildConfig {
+            schema_version: CURRENT_SCHEMA_VERSION,
             guild_id,
             moderation_channel: None.into(),
             raid_containment_channel: None.into(),
@@ -175,22 +262,28 @@ impl GuildConfig {
             member_role: None.into(),
             silence_role: None.into(),
             new_role: None.into(),
-            raid_trigger_timespan: 90.into(),
-            raid_trigger_new_user_limit: 5.into(),
-            raid_autoexpiration: 600.into(),
-            max_pressure: 60.0.into(),
-            message_pressure: 10.0.into(),
-            embed_pressure: 8.3.into(),
-            character_pressure: 0.00625.into(),
-            newline_pressure: 0.714.into(),
-            unique_ping_pressure: 2.5.into(),
-            pressure_decay_per_second: 8.0.into(),
+            admin_roles: Vec::new().into(),
+            raid_trigger_timespan: defaults.raid_trigger_timespan.into(),
+            raid_trigger_new_user_limit: defaults.raid_trigger_new_user_limit.into(),
+            raid_autoexpiration: defaults.raid_autoexpiration.into(),
+            max_pressure: defaults.max_pressure.into(),
+            message_pressure: defaults.message_pressure.into(),
+            embed_pressure: defaults.embed_pressure.into(),
+            character_pressure: defaults.character_pressure.into(),
+            newline_pressure: defaults.newline_pressure.into(),
+            unique_ping_pressure: defaults.unique_ping_pressure.into(),
+            pressure_decay_per_second: defaults.pressure_decay_per_second.into(),
+            warning_limit: 3.into(),
+            archive_retention_days: 30.into(),
+            history: Default::default(),
+            preset: None,
+            removed_at: None,
         };
         new.load_names();
         new
     }
 
-    fn load_names(&mut self) {
+    pub(crate) fn load_names(&mut self) {
         self.moderation_channel.name = "moderation_channel".into();
         self.raid_containment_channel.name = "raid_containment_channel".into();
         self.silence_containment_channel.name = "silence_containment_channel".into();
@@ -198,6 +291,7 @@ impl GuildConfig {
         self.member_role.name = "member_role".into();
         self.silence_role.name = "silence_role".into();
         self.new_role.name = "new_role".into();
+        self.admin_roles.name = "admin_roles".into();
         self.raid_trigger_timespan.name = "raid_trigger_timespan".into();
         self.raid_trigger_new_user_limit.name = "raid_trigger_new_user_limit".into();
         self.raid_autoexpiration.name = "raid_autoexpiration".into();
@@ -208,30 +302,68 @@ impl GuildConfig {
         self.newline_pressure.name = "newline_pressure".into();
         self.unique_ping_pressure.name = "unique_ping_pressure".into();
         self.pressure_decay_per_second.name = "pressure_decay_per_second".into();
+        self.warning_limit.name = "warning_limit".into();
+        self.archive_retention_days.name = "archive_retention_days".into();
     }
 
-    pub fn get_configurable_fields(&mut self) -> Vec<Box<&mut (dyn Configurable + Send + Sync)>> {
+    pub fn get_configurable_fields(&mut self) -> Vec<&mut (dyn Configurable + Send + Sync)> {
         vec![
-            Box::new(&mut self.moderation_channel),
-            Box::new(&mut self.raid_containment_channel),
-            Box::new(&mut self.silence_containment_channel),
-            Box::new(&mut self.log_channel),
-            Box::new(&mut self.member_role),
-            Box::new(&mut self.silence_role),
-            Box::new(&mut self.new_role),
-            Box::new(&mut self.raid_trigger_timespan),
-            Box::new(&mut self.raid_trigger_new_user_limit),
-            Box::new(&mut self.raid_autoexpiration),
-            Box::new(&mut self.max_pressure),
-            Box::new(&mut self.message_pressure),
-            Box::new(&mut self.embed_pressure),
-            Box::new(&mut self.character_pressure),
-            Box::new(&mut self.newline_pressure),
-            Box::new(&mut self.unique_ping_pressure),
-            Box::new(&mut self.pressure_decay_per_second),
+            &mut self.moderation_channel,
+            &mut self.raid_containment_channel,
+            &mut self.silence_containment_channel,
+            &mut self.log_channel,
+            &mut self.member_role,
+            &mut self.silence_role,
+            &mut self.new_role,
+            &mut self.admin_roles,
+            &mut self.raid_trigger_timespan,
+            &mut self.raid_trigger_new_user_limit,
+            &mut self.raid_autoexpiration,
+            &mut self.max_pressure,
+            &mut self.message_pressure,
+            &mut self.embed_pressure,
+            &mut self.character_pressure,
+            &mut self.newline_pressure,
+            &mut self.unique_ping_pressure,
+            &mut self.pressure_decay_per_second,
+            &mut self.warning_limit,
+            &mut self.archive_retention_days,
         ]
     }
 
+    /// Lists every field whose value differs in `other` as `(name, current value, other value)`.
+    pub(crate) fn diff(&mut self, other: &mut GuildConfig) -> Vec<(String, String, String)> {
+        self.get_configurable_fields().iter().zip(other.get_configurable_fields().iter())
+            .filter(|(current, new)| current.get_value() != new.get_value())
+            .map(|(current, new)| (current.get_name().clone(), current.get_value(), new.get_value()))
+            .collect()
+    }
+
+    /// The default settings for the guild. The admin roles are kept so admins can't lock themselves out,
+    /// and every reset value is recorded in the kept change history.
+    pub(crate) fn reset(mut self, actor: UserId) -> GuildConfig {
+        let mut defaults = GuildConfig::new(self.guild_id);
+        defaults.admin_roles = self.admin_roles.clone();
+        let changes = self.diff(&mut defaults);
+        defaults.history = self.history;
+        for (field, old_value, new_value) in changes {
+            defaults.history.record(ConfigChange { actor, timestamp: Utc::now(), field, old_value, new_value });
+        }
+        defaults
+    }
+
+    /// Collects a description of every field that is invalid for a guild with these roles and channels.
+    pub(crate) fn validate(&mut self, roles: &[&Role], channels: &[&GuildChannel]) -> Vec<String> {
+        self.get_configurable_fields().iter()
+            .filter_map(|field| field.validate(roles, channels).err().map(|e| format!("{}: {}", field.get_name(), e)))
+            .collect()
+    }
+
+    /// Whether the member may use configuration commands, either through an admin role or the Manage Guild permission.
+    pub(crate) fn is_admin(&self, member: &Member) -> bool {
+        is_admin(member, &self.admin_roles)
+    }
+
     pub(crate) fn setup_help(&self) -> String {
         let mut helptexts: Vec<String> = Default::default();
         helptexts.push("These are the recommended steps you should take. You can change values by using the `/change` or `/config` slash command.\n".into());
@@ -254,51 +386,57 @@ impl GuildConfig {
             helptexts.push("Consider setting the 'silence' role. This will be assigned to users who spam and will restrict their permissions".into())
         }
 
+        if self.admin_roles.is_empty() {
+            optional_steps.push("You can set admin roles to let moderators without the Manage Server permission configure Bussy.".into())
+        }
+
 
         helptexts.join("\n\n") + &optional_steps.join("\n\n")
     }
 }
-/*
-impl Loggable for LogData {
-    fn slog(&mut self, content: String) {
-        self.insert(Utc::now(), content);
+/// Whether the member may use configuration commands, either through one of the admin roles or the Manage Guild permission.
+pub(crate) fn is_admin(member: &Member, admin_roles: &[RoleId]) -> bool {
+    if let Some(permissions) = member.permissions {
+        if permissions.administrator() || permissions.manage_guild() {
+            return true;
+        }
     }
-}*/
+    member.roles.iter().any(|r| admin_roles.contains(r))
+}
 
 impl Loggable for LogData {
-    fn slog(&mut self, content: String) {
-        println!("New log: {}", &content);
-        self.records.insert(Utc::now(), content);
+    fn log(&mut self, entry: LogEntry) {
+        self.push(entry);
     }
 }
 
 impl Loggable for &mut LogData {
-    fn slog(&mut self, content: String) {
-        self.records.insert(Utc::now(), content);
+    fn log(&mut self, entry: LogEntry) {
+        self.push(entry);
     }
 }
 
 impl Loggable for &mut MemberShell {
-    fn slog(&mut self, content: String) {
-        self._log.slog(content);
+    fn log(&mut self, entry: LogEntry) {
+        self._log.push(entry);
     }
 }
 
 impl Loggable for MemberShell {
-    fn slog(&mut self, content: String) {
-        self._log.slog(content);
+    fn log(&mut self, entry: LogEntry) {
+        self._log.push(entry);
     }
 }
 
 impl Loggable for GuildShell {
-    fn slog(&mut self, content: String) {
-        self._log.slog(content);
+    fn log(&mut self, entry: LogEntry) {
+        self._log.push(entry);
     }
 }
 
 impl Loggable for &mut GuildShell {
-    fn slog(&mut self, content: String) {
-        self._log.slog(content);
+    fn log(&mut self, entry: LogEntry) {
+        self._log.push(entry);
     }
 }
 
@@ -310,8 +448,28 @@ pub struct GuildShell {
     last_raid: Option<RaidInfo>,
     pub(crate) active_members: HashMap<UserId, MemberShell>,
     pub(crate) _log: LogData,
-    pub(crate) config_component_id: Option<u32>,
     receiver: mpsc::Receiver<ShellEvent>,
+    pub(crate) store: Arc<dyn ShellStore>,
+    // When the config first changed since it was last saved
+    dirty_since: Option<Instant>,
+    // Same for the runtime state
+    state_dirty_since: Option<Instant>,
+    // Saved pressure of members who haven't been seen since the restart
+    restored_pressure: HashMap<UserId, MemberPressure>,
+    pending_silences: Vec<UserId>,
+    silence_expiries: HashMap<UserId, DateTime<Utc>>,
+    // Joins within the raid trigger timespan, oldest first
+    recent_joins: VecDeque<(DateTime<Utc>, UserId)>,
+    pub(crate) http: Arc<Http>,
+    pub(crate) last_log_flush: Instant,
+    pub(crate) cases: CaseLog,
+    // Saved together with the runtime state
+    pub(crate) user_records: UserRecords,
+    pub(crate) archive: MessageArchive,
+    pub(crate) message_cache: MessageCache,
+    pub(crate) presets: Arc<BTreeMap<String, ConfigPreset>>,
+    // Copy of the admin roles in the shell's contact, permission checks read it without asking the shell
+    shared_admin_roles: Arc<RwLock<Vec<RoleId>>>,
 }
 
 impl Serialize for GuildShell {
@@ -322,52 +480,116 @@ impl Serialize for GuildShell {
 
 
 impl GuildShell {
+    /// Starts the guild's shell, unless one is already running.
     pub async fn initialize(ctx: &Context, mut config: GuildConfig) {
-        println!("Initializing thread for {}!", config.guild_id);
         config.load_names();
+        // Back in the guild, the archived config is used again
+        config.removed_at = None;
         let (sender, receiver) = tokio::sync::mpsc::channel::<ShellEvent>(20);
+        let admin_roles = Arc::new(RwLock::new(config.admin_roles.to_vec()));
+        let (store, presets) = {
+            let data = ctx.data.read().await;
+            (data.get::<ShellStorage>().unwrap().clone(), data.get::<ConfigPresets>().unwrap().clone())
+        };
+
+        let guild_id = config.guild_id;
+        let state = match store.load_state(guild_id) {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) => {
+                warn!(%guild_id, "Couldn't restore the runtime state: {}", e);
+                RuntimeState::default()
+            }
+        };
+        let user_records = match store.load_user_records(guild_id) {
+            Ok(records) => records.unwrap_or_default(),
+            Err(e) => {
+                warn!(%guild_id, "Couldn't load the user records: {}", e);
+                UserRecords::default()
+            }
+        };
+        let cases = match store.load_cases(guild_id) {
+            Ok(cases) => cases.unwrap_or_default(),
+            Err(e) => {
+                warn!(%guild_id, "Couldn't load the cases: {}", e);
+                CaseLog::default()
+            }
+        };
+
+        let archive = match store.load_archive(guild_id) {
+            Ok(archive) => archive.unwrap_or_default(),
+            Err(e) => {
+                warn!(%guild_id, "Couldn't load the message archive: {}", e);
+                MessageArchive::default()
+            }
+        };
 
-        let guild_id = config.guild_id.clone();
         let mut new_shell = Box::new(GuildShell {
             config,
-            current_raid: None,
-            last_raid: None,
+            current_raid: state.current_raid,
+            last_raid: state.last_raid,
             active_members: Default::default(),
             _log: Default::default(),
-            config_component_id: None,
             receiver,
+            store,
+            dirty_since: None,
+            state_dirty_since: None,
+            restored_pressure: state.member_pressure,
+            pending_silences: Vec::new(),
+            silence_expiries: state.silence_expiries,
+            recent_joins: VecDeque::new(),
+            http: ctx.http.clone(),
+            last_log_flush: Instant::now(),
+            cases,
+            user_records,
+            archive,
+            message_cache: MessageCache::default(),
+            presets,
+            shared_admin_roles: admin_roles.clone(),
         });
 
-        let handle = tokio::spawn(async move { new_shell.listen().await });
+        // Checked and inserted under one lock, so a repeated guild_create can't orphan a running shell
+        let mut data = ctx.data.write().await;
+        let shells = data.get_mut::<GuildShells>().unwrap();
+        if shells.get(&guild_id).is_some_and(|shell| !shell.handle.is_finished()) {
+            debug!(%guild_id, "Shell is already running");
+            return;
+        }
+
+        let resume_ctx = ctx.clone();
+        let span = info_span!("shell", %guild_id);
+        let handle = tokio::spawn(async move {
+            info!("Shell started");
+            new_shell.resume_silences(&resume_ctx, state.pending_silences).await;
+            new_shell.listen().await
+        }.instrument(span));
         let _waker = AtomicWaker::new();
 
         let contact = ShellContact {
             channel: sender,
             handle,
+            admin_roles,
         };
-
-        let mut data = ctx.data.write().await;
-        let shells = data.get_mut::<GuildShells>().unwrap();
         shells.insert(guild_id, contact);
     }
 
     async fn handle_event(&mut self, event: ShellEvent) {
-        println!("Guild {} received {}", self.config.guild_id, event);
+        debug!("{}", event);
         let res = match event {
-            ShellEvent::NewMessage(ctx, msg) => {
-                let res = self.message_created(&ctx, &msg).await;
-                self.dump_logs(&ctx).await;
-                res
+            ShellEvent::NewMessage(ctx, msg) => self.message_created(&ctx, &msg).await,
+            ShellEvent::MessageEdited(ctx, event) => self.message_edited(&ctx, &event).await,
+            ShellEvent::MessageDeleted(channel, message_id) => {
+                self.message_deleted(channel, message_id);
+                Ok(())
             }
-            ShellEvent::MemberJoined(ctx, member) => {
-                let res = self.member_joined(&ctx, member).await;
-                self.dump_logs(&ctx).await;
-                res
+            ShellEvent::MemberJoined(ctx, member) => self.member_joined(&ctx, member).await,
+            ShellEvent::MemberLeft(user, member) => {
+                self.member_left(&user, member);
+                Ok(())
             }
-            ShellEvent::NewInteraction(ctx, interaction) => {
-                let res = self.handle_interaction(&ctx, &interaction).await;
-                self.dump_logs(&ctx).await;
-                res
+            ShellEvent::NewInteraction(ctx, interaction) => self.handle_interaction(&ctx, &interaction).await,
+            ShellEvent::LoadConfig(actor, config) => {
+                self.load_config(actor, config);
+                Ok(())
             }
             ShellEvent::GetConfig(sender) => {
                 if sender.send(self.config.clone()).is_ok() {
@@ -378,11 +600,115 @@ impl GuildShell {
             }
         };
         if let Err(e) = res {
-            self.slog(e.to_string());
+            self.log(LogEntry::Error { message: e.to_string() });
+        }
+    }
+
+    /// Publishes the config's admin roles to the shell's contact, call it whenever they may have changed.
+    pub(crate) fn share_admin_roles(&self) {
+        *self.shared_admin_roles.write().unwrap() = self.config.admin_roles.to_vec();
+    }
+
+    /// Schedules the config to be saved, see `SAVE_DELAY`.
+    pub(crate) fn mark_dirty(&mut self) {
+        if self.dirty_since.is_none() {
+            self.dirty_since = Some(Instant::now());
+        }
+    }
+
+    /// Schedules the runtime state to be saved, see `STATE_SAVE_DELAY`.
+    pub(crate) fn mark_state_dirty(&mut self) {
+        if self.state_dirty_since.is_none() {
+            self.state_dirty_since = Some(Instant::now());
+        }
+    }
+
+    fn runtime_state(&self) -> RuntimeState {
+        let mut member_pressure = self.restored_pressure.clone();
+        for (id, member) in &self.active_members {
+            if let Some(pressure) = member.saved_pressure(*self.config.pressure_decay_per_second) {
+                member_pressure.insert(*id, pressure);
+            }
+        }
+
+        RuntimeState {
+            current_raid: self.current_raid.clone(),
+            last_raid: self.last_raid.clone(),
+            member_pressure,
+            pending_silences: self.pending_silences.clone(),
+            silence_expiries: self.silence_expiries.clone(),
+        }
+    }
+
+    /// Saves the runtime state if it changed since it was last saved.
+    async fn persist_state(&mut self) {
+        if self.state_dirty_since.is_none() {
+            return;
+        }
+        self.user_records.prune();
+        let (guild_id, state, user_records) = (self.config.guild_id, self.runtime_state(), self.user_records.clone());
+        let saved = blocking(&self.store, move |store| {
+            store.save_state(guild_id, &state).and_then(|_| store.save_user_records(guild_id, &user_records))
+        }).await;
+        match saved {
+            Ok(()) => self.state_dirty_since = None,
+            Err(e) => {
+                self.state_dirty_since = Some(Instant::now());
+                self.log_error("Saving the runtime state failed", e);
+            }
+        }
+    }
+
+    fn has_pending_logs(&self) -> bool {
+        !self._log.is_empty() || self.active_members.values().any(|member| !member._log.is_empty())
+    }
+
+    /// When the next pending save, log flush or silence expiry is due, if any.
+    fn next_deadline(&self) -> Option<Instant> {
+        let config = self.dirty_since.map(|since| since + SAVE_DELAY);
+        let state = self.state_dirty_since.map(|since| since + STATE_SAVE_DELAY);
+        let logs = self.has_pending_logs().then(|| self.last_log_flush + LOG_FLUSH_INTERVAL);
+        let silence = self.silence_expiries.values().min()
+            .map(|expires| Instant::now() + (*expires - Utc::now()).to_std().unwrap_or_default());
+        config.into_iter().chain(state).chain(logs).chain(silence).min()
+    }
+
+    async fn persist_due(&mut self) {
+        let now = Instant::now();
+        if self.dirty_since.is_some_and(|since| since + SAVE_DELAY <= now) {
+            self.persist().await;
+        }
+        if self.state_dirty_since.is_some_and(|since| since + STATE_SAVE_DELAY <= now) {
+            self.persist_state().await;
+        }
+    }
+
+    /// Finishes silences that were interrupted by a restart.
+    async fn resume_silences(&mut self, ctx: &Context, pending: Vec<UserId>) {
+        for user_id in pending {
+            self.log(LogEntry::SilenceResumed { user_id });
+            self.enforce_silence(ctx, &user_id, true).await;
         }
     }
 
-    async fn listen(&mut self) {
+    /// Saves the config if it changed since it was last saved.
+    async fn persist(&mut self) {
+        if self.dirty_since.is_none() {
+            return;
+        }
+        let config = self.config.clone();
+        match blocking(&self.store, move |store| store.save(&config)).await {
+            Ok(()) => self.dirty_since = None,
+            Err(e) => {
+                // Try again after another delay instead of on every loop iteration
+                self.dirty_since = Some(Instant::now());
+                self.log_error("Saving the config failed", e);
+            }
+        }
+    }
+
+    /// Handles events until the channel is closed, then saves everything and returns the final config.
+    async fn listen(&mut self) -> GuildConfig {
         /*loop {
             let ev = self.receiver.try_recv();
             match ev {
@@ -400,53 +726,173 @@ impl GuildShell {
 
          */
 
-        while let Some(event) = self.receiver.recv().await {
-            self.handle_event(event).await
+        loop {
+            // Changes are batched and saved a while after the first one, logs are batched the same way
+            let event = match self.next_deadline() {
+                Some(deadline) => {
+                    tokio::select! {
+                        event = self.receiver.recv() => event,
+                        _ = tokio::time::sleep_until(deadline) => {
+                            self.expire_silences().await;
+                            self.persist_due().await;
+                            if self.last_log_flush + LOG_FLUSH_INTERVAL <= Instant::now() {
+                                self.dump_logs().await;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                None => self.receiver.recv().await
+            };
+
+            match event {
+                Some(event) => {
+                    let span = info_span!("event", kind = event.kind(), user_id = event.user_id().map(|id| id.0));
+                    self.handle_event(event).instrument(span).await
+                }
+                None => break
+            }
         }
-        println!("Exiting listener for {}", self.config.guild_id);
+        self.persist().await;
+        self.persist_state().await;
+        self.dump_logs().await;
+        info!("Shell stopped");
+        std::mem::take(&mut self.config)
     }
 
-    pub fn get_config(&self) -> &GuildConfig {
-        &self.config
+    /// Current pressure of the member, if they sent messages since bussy started.
+    pub(crate) fn member_pressure(&self, user_id: UserId) -> Option<f64> {
+        self.active_members.get(&user_id).map(|member| member.current_pressure(*self.config.pressure_decay_per_second))
     }
 
     pub fn calculate_message_pressure(&self, msg: &Message) -> f64 {
+        self.calculate_pressure(&msg.content, msg.embeds.len(), msg.mentions.len())
+    }
+
+    /// Pressure of a message with this content, also used to re-score edits.
+    pub(crate) fn calculate_pressure(&self, content: &str, embeds: usize, mentions: usize) -> f64 {
         let mut pressure: f64 = *self.config.message_pressure;
-        pressure += *self.config.embed_pressure * msg.embeds.len() as f64;
-        pressure += *self.config.character_pressure * msg.content.len() as f64;
-        pressure += *self.config.newline_pressure * msg.content.matches("\n").collect::<String>().len() as f64;
-        pressure += *self.config.unique_ping_pressure * msg.mentions.len() as f64;
+        pressure += *self.config.embed_pressure * embeds as f64;
+        pressure += *self.config.character_pressure * content.len() as f64;
+        pressure += *self.config.newline_pressure * content.matches("\n").collect::<String>().len() as f64;
+        pressure += *self.config.unique_ping_pressure * mentions as f64;
 
         pressure
     }
 
+    /// Ends the current raid once it is older than `raid_autoexpiration`.
+    fn expire_raid(&mut self) {
+        let expiration = chrono::Duration::seconds(*self.config.raid_autoexpiration as i64);
+        if self.current_raid.as_ref().is_some_and(|raid| raid.raid_started + expiration <= Utc::now()) {
+            let raid = self.current_raid.take().unwrap();
+            self.log(LogEntry::RaidEnded { raiders: raid.raiders.len(), started: raid.raid_started });
+            self.last_raid = Some(raid);
+            self.mark_state_dirty();
+        }
+    }
+
+    /// Starts a raid when `raid_trigger_new_user_limit` members joined within `raid_trigger_timespan`.
+    fn track_join(&mut self, user_id: UserId) {
+        self.expire_raid();
+        let now = Utc::now();
+        let timespan = chrono::Duration::seconds(*self.config.raid_trigger_timespan as i64);
+        self.recent_joins.push_back((now, user_id));
+        while self.recent_joins.front().is_some_and(|(joined, _)| *joined + timespan < now) {
+            self.recent_joins.pop_front();
+        }
+
+        if self.current_raid.is_none() && self.recent_joins.len() >= *self.config.raid_trigger_new_user_limit as usize {
+            let raiders: Vec<UserId> = self.recent_joins.iter().map(|(_, id)| *id).collect();
+            for raider in &raiders {
+                self.user_records.entry(*raider).raids += 1;
+            }
+            self.log(LogEntry::RaidStarted { raiders: raiders.len() });
+            self.current_raid = Some(RaidInfo { raid_started: now, raiders });
+            self.mark_state_dirty();
+        }
+    }
+
     pub async fn member_joined(&mut self, ctx: &Context, new_member: Member) -> Result<(), SerenityError> {
-        let new_member_id = new_member.user.id.clone();
+        let new_member_id = new_member.user.id;
+        // Leaving while silenced or raiding is remembered so it can't be used to shed the silence role
+        let departure = self.user_records.get(new_member_id).and_then(|record| record.departures.last())
+            .filter(|departure| departure.silenced || departure.during_raid).cloned();
+        self.user_records.entry(new_member_id).joins.push(Utc::now());
+        self.mark_state_dirty();
+        self.track_join(new_member_id);
         let mut _shell = MemberShell::from(new_member);
         self.active_members.insert(new_member_id, _shell);
         let shell: &mut MemberShell = self.active_members.get_mut(&new_member_id).dexpect("You should never see this. (member shell inserted but missing)", &mut self._log);
 
         if let Some(raid) = &mut self.current_raid {
-            raid.raiders.push(new_member_id);
-            shell.log("Joined during raid! No automatic role assignment");
+            // The member who triggered the raid is already in it
+            if !raid.raiders.contains(&new_member_id) {
+                raid.raiders.push(new_member_id);
+                self.user_records.entry(new_member_id).raids += 1;
+            }
+            shell.log(LogEntry::JoinedDuringRaid);
         } else {
             if let Some(member_role) = &*self.config.member_role {
                 match shell.member.add_role(ctx, member_role).await {
-                    Ok(_) => shell.log("Member role added"),
-                    Err(e) => shell.slog(format!("Adding member role failed! reason: {}", e))
+                    Ok(_) => shell.log(LogEntry::RoleAssigned { role: *member_role }),
+                    Err(e) => shell.log_error("Adding member role failed", e)
                 }
-            } else { shell.log("Member role not configured so not assigned.") }
+            } else { shell.log(LogEntry::NotConfigured { field: "member_role" }) }
             if let Some(new_role) = &*self.config.new_role {
                 match shell.member.add_role(ctx, new_role).await {
-                    Ok(_) => shell.log("'New' role added"),
-                    Err(e) => shell.slog(format!("Adding 'new' role failed! reason: {}", e))
+                    Ok(_) => shell.log(LogEntry::RoleAssigned { role: *new_role }),
+                    Err(e) => shell.log_error("Adding 'new' role failed", e)
+                }
+            } else { shell.log(LogEntry::NotConfigured { field: "new_role" }) }
+        }
+
+        if let Some(departure) = departure {
+            let rejoined = LogEntry::Rejoined { left: departure.timestamp, silenced: departure.silenced, during_raid: departure.during_raid };
+            let mut flag = format!("<@{}> {}.", new_member_id, rejoined);
+            shell.log(rejoined);
+            if departure.silenced {
+                match *self.config.silence_role {
+                    Some(silence_role) => match shell.member.add_role(ctx, silence_role).await {
+                        Ok(_) => {
+                            shell.log(LogEntry::SilenceReapplied);
+                            flag.push_str(" Their silence was reapplied.");
+                        }
+                        Err(e) => shell.log_error("Reapplying the silence role failed", e)
+                    }
+                    None => shell.log(LogEntry::NotConfigured { field: "silence_role" })
                 }
-            } else { shell.log("'New'' role not configured so not assigned.") }
+            }
+            self.flag_to_moderators(ctx, flag).await;
         }
         Ok(())
     }
 
-    async fn ensure_member_shell(&mut self, ctx: &Context, user_id: UserId) -> Result<(), Error> {
+    /// Remembers whether the member left while silenced or raiding, see `member_joined`.
+    pub fn member_left(&mut self, user: &User, member: Option<Member>) {
+        self.expire_raid();
+        let member = member.or_else(|| self.active_members.get(&user.id).map(|shell| shell.member.clone()));
+        let has_silence_role = self.config.silence_role.is_some_and(|role| member.is_some_and(|member| member.roles.contains(&role)));
+        let silenced = has_silence_role || self.pending_silences.contains(&user.id) || self.silence_expiries.contains_key(&user.id);
+        let during_raid = self.current_raid.as_ref().is_some_and(|raid| raid.raiders.contains(&user.id));
+
+        self.user_records.entry(user.id).departures.push(Departure { timestamp: Utc::now(), silenced, during_raid });
+        self.mark_state_dirty();
+        self.log(LogEntry::MemberLeft { user_id: user.id, silenced, during_raid });
+    }
+
+    /// Posts in the moderation channel.
+    async fn flag_to_moderators(&mut self, ctx: &Context, text: String) {
+        match *self.config.moderation_channel {
+            Some(channel) => {
+                if let Err(e) = channel.say(ctx, text).await {
+                    self.log_error("Posting in the moderation channel failed", e);
+                }
+            }
+            None => self.log(LogEntry::NotConfigured { field: "moderation_channel" })
+        }
+    }
+
+    pub(crate) async fn ensure_member_shell(&mut self, ctx: &Context, user_id: UserId) -> Result<(), Error> {
         let active_members = &mut self.active_members;
 
         if active_members.contains_key(&user_id) {
@@ -455,85 +901,176 @@ impl GuildShell {
 
         match self.config.guild_id.member(&ctx, user_id).await {
             Ok(m) => {
-                let sh = MemberShell::from(m);
+                let mut sh = MemberShell::from(m);
+                if let Some(saved) = self.restored_pressure.remove(&user_id) {
+                    sh.restore_pressure(saved);
+                }
                 active_members.insert(user_id, sh);
                 Ok(())
             }
             Err(e) => {
-                println!("Coudlnt get member wtf {}", e);
-                return Err(e);
+                warn!(%user_id, "Couldn't fetch member: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Silences the member and opens a case for it. Without a duration the silence lasts until it is lifted.
+    pub async fn silence_member(&mut self, ctx: &Context, user_id: &UserId, actor: Actor, reason: Option<String>, duration: Option<chrono::Duration>,
+                                purge_messages: bool) {
+        if self.ensure_member_shell(ctx, *user_id).await.is_ok() && !self.active_members[user_id].cleanup_in_progress {
+            let pressure = self.active_members[user_id].current_pressure;
+            self.user_records.entry(*user_id).silences += 1;
+            let expires = duration.map(|duration| Utc::now() + duration);
+            match expires {
+                Some(expires) => self.silence_expiries.insert(*user_id, expires),
+                None => self.silence_expiries.remove(user_id)
+            };
+            self.mark_state_dirty();
+            let number = self.open_case(*user_id, actor, CaseAction::Silence, reason, Some(pressure)).await;
+            if expires.is_some() {
+                if let Some(case) = self.cases.get_mut(number) {
+                    case.expires = expires;
+                }
+                self.save_cases().await;
+            }
+        }
+        self.enforce_silence(ctx, user_id, purge_messages).await;
+    }
+
+    /// Removes the silence role again.
+    pub(crate) async fn lift_silence(&mut self, user_id: UserId, actor: Actor) -> Result<(), String> {
+        let silence_role = (*self.config.silence_role).ok_or("The silence role is not configured")?;
+        match self.http.remove_member_role(self.config.guild_id.0, user_id.0, silence_role.0).await {
+            Ok(()) => {}
+            // The member left or the role is gone, either way there is no role left to remove
+            Err(Error::Http(e)) if e.status_code().is_some_and(|status| status.as_u16() == 404) => {}
+            Err(e) => return Err(e.to_string())
+        }
+
+        // Only forgotten once the role is off, so a failed lift is retried
+        if self.silence_expiries.remove(&user_id).is_some() {
+            self.mark_state_dirty();
+        }
+        // Members who left while silenced are not silenced again when they come back
+        if let Some(departure) = self.user_records.entry(user_id).departures.last_mut() {
+            departure.silenced = false;
+            self.mark_state_dirty();
+        }
+        self.log(LogEntry::SilenceLifted { user_id, actor });
+        Ok(())
+    }
+
+    /// Lifts the timed silences that are over.
+    async fn expire_silences(&mut self) {
+        let now = Utc::now();
+        let expired: Vec<UserId> = self.silence_expiries.iter().filter(|(_, expires)| **expires <= now).map(|(id, _)| *id).collect();
+        for user_id in expired {
+            if let Err(e) = self.lift_silence(user_id, Actor::Bot).await {
+                self.log_error(&format!("The silence of {} could not be lifted, retrying in {} minutes", user_id, SILENCE_LIFT_RETRY_MINUTES), e);
+                self.silence_expiries.insert(user_id, now + chrono::Duration::minutes(SILENCE_LIFT_RETRY_MINUTES));
+                self.mark_state_dirty();
             }
         }
     }
 
-    pub async fn silence_member(&mut self, ctx: &Context, user_id: &UserId) {
-        let is_shell = self.ensure_member_shell(&ctx, user_id.clone()).await;
+    /// Assigns the silence role and, if asked to, deletes the member's recent messages.
+    async fn enforce_silence(&mut self, ctx: &Context, user_id: &UserId, purge_messages: bool) {
+        let is_shell = self.ensure_member_shell(ctx, *user_id).await;
 
         if is_shell.is_ok() {
+            if self.active_members[user_id].cleanup_in_progress { return; } // lock member so we don't try to delete nonexisting messages
+
+            // Saved right away so a restart in the middle of this finishes the silence afterwards
+            if !self.pending_silences.contains(user_id) {
+                self.pending_silences.push(*user_id);
+            }
+            self.mark_state_dirty();
+            self.persist_state().await;
+
             let shell = self.active_members.get_mut(user_id).unwrap();
-            if shell.cleanup_in_progress { return; } // lock member so we don't try to delete nonexisting messages
             shell.cleanup_in_progress = true;
 
             if let Some(silence_role) = *self.config.silence_role {
                 match shell.member.add_role(&ctx, silence_role).await {
-                    Ok(_resp) => shell.log("Member silenced successfully"),
-                    Err(e) => shell.slog(format!("Member could not be silenced! {}", e))
+                    Ok(_resp) => shell.log(LogEntry::MemberSilenced { user_id: *user_id }),
+                    Err(e) => shell.log_error("Member could not be silenced", e)
                 }
             } else {
-                shell.log("Silence role is not configured! Could not silence.");
+                shell.log(LogEntry::NotConfigured { field: "silence_role" });
             }
 
-            let mut deletion_failed = None;
-            let mut channel_map: HashMap<ChannelId, Vec<MessageId>> = HashMap::default();
-            for msg in &shell.recent_messages {
-                if !channel_map.contains_key(&msg.1) {
-                    channel_map.insert(msg.1, Vec::new());
+            let mut snapshots = Vec::new();
+            if purge_messages {
+                let messages = std::mem::take(&mut shell.recent_messages);
+                let mut missing = Vec::new();
+                // Taken out of the cache so their deletion isn't logged
+                for location in &messages {
+                    match self.message_cache.remove(location.0) {
+                        Some(snapshot) => snapshots.push(snapshot),
+                        None => missing.push(*location)
+                    }
                 }
-                channel_map.get_mut(&msg.1).unwrap().push(msg.0);
-
-                /*match ctx.http.delete_message(msg.1.into(), msg.0.into()).await {
-                    Ok(()) => println!("Deleting a message!"),
-                    Err(e) => deletion_failed = Some(e)
+                // Only the ids of messages from before a restart or too old for the cache are known
+                for (message_id, channel_id) in missing {
+                    match channel_id.message(&ctx, message_id).await {
+                        Ok(message) => snapshots.push(MessageSnapshot::from(&message)),
+                        Err(e) => debug!(%message_id, "Couldn't fetch message to archive: {}", e)
+                    }
                 }
 
-                 */
-            }
-
-            for (channel, messages) in channel_map {
-                if let Err(e) = channel.delete_messages(&ctx, messages).await {
-                    deletion_failed = Some(e);
+                if let (_, Some(e)) = delete_messages(&ctx.http, &messages).await {
+                    shell.log_error("Deleting messages failed", e);
                 }
             }
 
+            shell.cleanup_in_progress = false;
+            self.pending_silences.retain(|id| id != user_id);
+            self.mark_state_dirty();
 
-            if let Some(_err) = deletion_failed {
-                // self._log.slog(format!("Deleting a message failed: {}", err))
+            if !snapshots.is_empty() {
+                let transcript = self.archive_messages(&snapshots, &format!("Silence of {}", user_id)).await;
+                if let Some(shell) = self.active_members.get_mut(user_id) {
+                    shell._log.attach(format!("silence-{}-{}.txt", user_id, Utc::now().format("%Y%m%d-%H%M%S")), transcript);
+                }
             }
-
-            shell.cleanup_in_progress = false;
         } else {
-            self.log("This member could not be silenced (member shell could not be ensured)");
+            self.log(LogEntry::Error { message: format!("Member {} could not be silenced, they could not be fetched", user_id) });
         }
-        if self.dump_logs(&ctx).await.is_err() {}
     }
 
     pub async fn message_created(&mut self, ctx: &Context, message: &Message) -> Result<(), SerenityError> {
-        if self.ensure_member_shell(&ctx, message.author.id).await.is_ok() {
-            let pressure = self.calculate_message_pressure(&message);
-            let shell = self.active_members.get_mut(&message.author.id).unwrap();
-            let pressure = shell.update_pressure(&self.config.pressure_decay_per_second, &pressure);
-
-            shell.recent_messages.push((message.id, message.channel_id));
-
-            if pressure > *self.config.max_pressure {
-                shell.slog(format!("Member surpassed the pressure limit of {}", *self.config.max_pressure as i64));
-                self.silence_member(&ctx, &message.author.id).await;
-            } else if pressure > *self.config.max_pressure * 0.0 {
-                println!("Pressure for member {} is {}", shell.member.user.id, pressure);
-            }
+        self.expire_raid();
+        if self.ensure_member_shell(ctx, message.author.id).await.is_ok() {
+            self.mark_state_dirty();
+            let pressure = self.calculate_message_pressure(message);
+            self.message_cache.insert(MessageSnapshot::from(message));
+            self.user_records.entry(message.author.id).message_count += 1;
+            self.add_pressure(ctx, message.author.id, (message.id, message.channel_id), pressure).await;
             Ok(())
         } else {
             Err(serenity::Error::Other("Member shell couldn't be ensured :("))
         }
     }
+
+    /// Adds the pressure of the message to its author, who must have a member shell, and silences
+    /// them once it surpasses `max_pressure`.
+    pub(crate) async fn add_pressure(&mut self, ctx: &Context, user_id: UserId, message: MessageLocation, added: f64) {
+        let shell = self.active_members.get_mut(&user_id).unwrap();
+        let pressure = shell.update_pressure(&self.config.pressure_decay_per_second, &added);
+        if !shell.recent_messages.contains(&message) {
+            shell.recent_messages.push(message);
+        }
+
+        let record = self.user_records.entry(user_id);
+        record.peak_pressure = record.peak_pressure.max(pressure);
+
+        if pressure > *self.config.max_pressure {
+            shell.log(LogEntry::PressureExceeded { pressure, limit: *self.config.max_pressure });
+            let reason = format!("Pressure surpassed the limit of {:.0}", *self.config.max_pressure);
+            self.silence_member(ctx, &user_id, Actor::Bot, Some(reason), None, true).await;
+        } else if pressure > *self.config.max_pressure * 0.0 {
+            debug!(%user_id, pressure, "Pressure updated");
+        }
+    }
 }
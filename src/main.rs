@@ -1,9 +1,9 @@
 use std::collections::HashMap;
-use std::env;
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
 use std::sync::Arc;
 
+use chrono::Utc;
+use clap::Parser;
 use dotenv::dotenv;
 use serenity::{
     async_trait,
@@ -12,8 +12,9 @@ use serenity::{
     model::{
         gateway::Ready,
         interactions::{
-            application_command::ApplicationCommand,
+            application_command::{ApplicationCommand, ApplicationCommandInteraction},
             Interaction,
+            InteractionApplicationCommandCallbackDataFlags,
             InteractionResponseType,
         },
     },
@@ -22,35 +23,69 @@ use serenity::{
 use serenity::client::bridge::gateway::GatewayIntents;
 use serenity::framework::standard::CommandResult;
 use serenity::framework::StandardFramework;
+use serenity::builder::CreateApplicationCommands;
+use serenity::http::Http;
+use serenity::futures::future::join_all;
 use serenity::model::channel::Message;
-use serenity::model::guild::{Guild, Member};
-use serenity::model::id::{ChannelId, GuildId};
-use serenity::model::interactions::application_command::ApplicationCommandOptionType;
+use serenity::model::guild::{Guild, GuildUnavailable, Member};
+use serenity::model::user::User;
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::model::interactions::application_command::{ApplicationCommandOptionType, ApplicationCommandType};
 use tokio::sync::*;
 use tokio::task::JoinHandle;
+use tracing::{debug, error, info, trace, warn};
 
+use cli::{Cli, Command};
 use guild_shell::*;
+use moderation_log::LogData;
+use presets::ConfigPresets;
+use settings_io::PendingSettings;
+use storage::ShellStorage;
 
 
 
+mod archive;
+mod cases;
+mod cli;
 mod guild_shell;
+mod message_cache;
 mod config_form;
+mod config_history;
 mod error_handling;
+mod moderation;
+mod moderation_log;
+mod presets;
+mod purge;
+mod schema;
+mod settings_io;
+mod storage;
+mod user_records;
 
 struct ShellContact {
     channel: mpsc::Sender<ShellEvent>,
-    handle: JoinHandle<()>,
+    // Finishes with the shell's last config once the channel is closed
+    handle: JoinHandle<GuildConfig>,
+    // Kept up to date by the shell, so permissions can be checked while it is busy or stuck
+    admin_roles: Arc<std::sync::RwLock<Vec<RoleId>>>,
 }
 
-type ShellMap = HashMap<GuildId, ShellContact>;
-
 struct Handler;
 
+/// How often archived configs are checked for deletion while bussy runs.
+const EXPIRED_CONFIG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+// Events are moved through the shell channel once, so boxing the big ones buys nothing
+#[allow(clippy::large_enum_variant)]
 enum ShellEvent {
     NewMessage(Context, Message),
+    MessageEdited(Context, MessageUpdateEvent),
+    MessageDeleted(ChannelId, MessageId),
     MemberJoined(Context, Member),
+    MemberLeft(User, Option<Member>),
     NewInteraction(Context, Interaction),
     GetConfig(oneshot::Sender<GuildConfig>),
+    LoadConfig(UserId, GuildConfig),
 }
 
 impl Display for ShellEvent {
@@ -58,14 +93,53 @@ impl Display for ShellEvent {
         f.write_str(
             match self {
                 ShellEvent::NewMessage(_, _) => { "Event: New message" }
+                ShellEvent::MessageEdited(_, _) => { "Event: Message edited" }
+                ShellEvent::MessageDeleted(_, _) => { "Event: Message deleted" }
                 ShellEvent::MemberJoined(_, _) => { "Event: Member joined" }
+                ShellEvent::MemberLeft(_, _) => { "Event: Member left" }
                 ShellEvent::NewInteraction(_, _) => { "Event: New interaction" }
                 ShellEvent::GetConfig(_) => { "Event: Config requested" }
+                ShellEvent::LoadConfig(_, _) => { "Event: Config loaded" }
             }
         )
     }
 }
 
+impl ShellEvent {
+    /// Name of the event in logs.
+    fn kind(&self) -> &'static str {
+        match self {
+            ShellEvent::NewMessage(_, _) => "new_message",
+            ShellEvent::MessageEdited(_, _) => "message_edited",
+            ShellEvent::MessageDeleted(_, _) => "message_deleted",
+            ShellEvent::MemberJoined(_, _) => "member_joined",
+            ShellEvent::MemberLeft(_, _) => "member_left",
+            ShellEvent::NewInteraction(_, _) => "new_interaction",
+            ShellEvent::GetConfig(_) => "get_config",
+            ShellEvent::LoadConfig(_, _) => "load_config",
+        }
+    }
+
+    /// The user who caused the event, if any.
+    fn user_id(&self) -> Option<UserId> {
+        match self {
+            ShellEvent::NewMessage(_, msg) => Some(msg.author.id),
+            ShellEvent::MessageEdited(_, event) => event.author.as_ref().map(|author| author.id),
+            ShellEvent::MessageDeleted(_, _) => None,
+            ShellEvent::MemberJoined(_, member) => Some(member.user.id),
+            ShellEvent::MemberLeft(user, _) => Some(user.id),
+            ShellEvent::NewInteraction(_, interaction) => match interaction {
+                Interaction::ApplicationCommand(cmd) => Some(cmd.user.id),
+                Interaction::MessageComponent(cmp) => Some(cmp.user.id),
+                Interaction::Autocomplete(auto) => Some(auto.user.id),
+                Interaction::Ping(_) => None,
+            },
+            ShellEvent::GetConfig(_) => None,
+            ShellEvent::LoadConfig(actor, _) => Some(*actor),
+        }
+    }
+}
+
 struct GuildShells {}
 
 impl TypeMapKey for GuildShells {
@@ -77,6 +151,11 @@ struct BaseConfigData {
     bot_token: String,
     application_id: u64,
     shell_config_file: String,
+    command_prefix: String,
+    intents: GatewayIntents,
+    register_commands: bool,
+    dev_guild: Option<GuildId>,
+    removed_guild_days: u32,
 }
 
 impl TypeMapKey for BaseConfigData {
@@ -92,88 +171,44 @@ struct General;
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, mut ctx: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
-        let mut some_config = GuildConfig::new(0.into());
-
-        let commands;
-        if true {
-            commands = ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
-                commands
-                    .create_application_command(|command| {
-                        command.name("ping").description("A ping command")
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("config").description("Configure the server settings for bussy")
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("reset_guild_shell").description("Resets the guild settings to default values")
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("load_settings").description("Load settings from a raw JSON data")
-                            .create_option(|opt| {
-                                opt.name("settings").description("Settings to load").kind(ApplicationCommandOptionType::String)
-                            })
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("dump_settings").description("Dumps the current descriptions as a JSON file")
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("change").description("Change a setting manually");
-                        for field in some_config.get_configurable_fields() {
-                            field.add_slash_command_subcommand(cmd);
-                        }
-                        cmd
-                    })
-                    .create_application_command(|cmd| {
-                        cmd.name("setup").description("Get help setting up Bussy for best experience")
-                    })
-            })
-                .await;
+        info!("{} is connected!", ready.user.name);
+        let preset_names: Vec<String> = ctx.data.read().await.get::<ConfigPresets>().unwrap().keys().cloned().collect();
+
+        let (register, dev_guild) = {
+            let data = ctx.data.read().await;
+            let base = data.get::<BaseConfigData>().unwrap();
+            (base.register_commands, base.dev_guild)
+        };
+
+        let commands = if register {
+            register_commands(&ctx.http, &preset_names, dev_guild).await
         } else {
-            commands = ApplicationCommand::get_global_application_commands(&ctx.http).await;
-            println!("Commands were not updated")
-        }
-        println!("There is {} commands registered", commands.expect("Commands failed to retrieve.").len());
+            info!("Commands were not updated");
+            match dev_guild {
+                Some(guild_id) => guild_id.get_application_commands(&ctx.http).await,
+                None => ApplicationCommand::get_global_application_commands(&ctx.http).await
+            }
+        };
+        info!("There are {} commands registered", commands.expect("Commands failed to retrieve.").len());
 
 
-        load_shells(&mut ctx).await;
+        let current: Vec<GuildId> = ready.guilds.iter().map(|guild| guild.id()).collect();
+        load_shells(&mut ctx, &current).await;
 
         // Ensure a shell for all guilds
         let guilds = ctx.cache.guilds().await;
-        let mut data = ctx.data.write().await;
-        let shells = data.get_mut::<GuildShells>().unwrap();
-
-        for id in guilds {
-            if !shells.contains_key(&id) {
-                GuildShell::initialize(&ctx, GuildConfig::new(id)).await;
-                println!("Shell created for guild {} on start", id);
-            }
+        let missing: Vec<GuildId> = {
+            let data = ctx.data.read().await;
+            let shells = data.get::<GuildShells>().unwrap();
+            guilds.into_iter().filter(|id| !shells.contains_key(id)).collect()
+        };
+
+        for id in missing {
+            GuildShell::initialize(&ctx, stored_config(&ctx, id).await).await;
+            info!(guild_id = %id, "Shell created on start");
         }
-        drop(data);
-
-        save_shells(&mut ctx.data).await;
 
-        /*
-        let loopctx = ctx.clone();
-        let mut interval_timer = tokio::time::interval(chrono::Duration::seconds(5).to_std().unwrap());
-
-
-
-        tokio::spawn(async move {
-            loop {
-                interval_timer.tick().await;
-                // println!("Tick");
-                let mut data = loopctx.data.write().await;
-                let shells = data.get_mut::<GuildShells>().unwrap();
-                for shell in shells.values_mut() {
-                    if !shell.dump_logs(&loopctx.clone()).await.is_ok() {
-                        println!("Couldn't dump logs for {}!", shell.config.guild_id);
-                    }
-                }
-            }
-        });
-
-         */
+        save_shells(&mut ctx.data).await;  // Instant check whether the file can also be saved.
     }
 
     async fn guild_member_addition(&self, ctx: Context, _guild_id: GuildId, new_member: Member) {
@@ -181,16 +216,22 @@ impl EventHandler for Handler {
             return;
         }
 
-        let mut data = ctx.data.write().await;
-        if let Some(target_guild) = data.get_mut::<GuildShells>().unwrap().get_mut(&_guild_id) {
-            target_guild.channel.send(ShellEvent::MemberJoined(ctx.clone(), new_member)).await;
-        } else {
-            println!("AAA guild shell not set up for guild id {}", _guild_id);  // TODO: implement better error handling
-        }
+        send_to_shell(&ctx, _guild_id, ShellEvent::MemberJoined(ctx.clone(), new_member)).await;
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        println!("INTERACTION RECEIVED");
+        debug!(kind = ?interaction.kind(), "Interaction received");
+        if !is_authorized(&ctx, &interaction).await {
+            deny_interaction(&ctx, &interaction).await;
+            return;
+        }
+
+        if let Interaction::MessageComponent(ref component) = interaction {
+            if settings_io::resolve_settings(&ctx, component).await {
+                return;
+            }
+        }
+
         if let Interaction::ApplicationCommand(ref command) = interaction {
             // let author = command.member.expect("Command author");
             // let guild = ctx.cache().expect("Cache not present").guild(author.guild_id).await.expect("Guild not retrievable");
@@ -201,44 +242,23 @@ impl EventHandler for Handler {
                     "Pong!".into()
                 }
                 "reset_guild_shell" => {
-                    match _recreate_shell(&ctx, &command.guild_id.expect("Must be used in a guild")).await {
-                        Ok(_) => "cool".into(),
-                        Err(e) => format!("Not cool :( {}", e)
+                    if let Err(e) = reset_guild_shell(&ctx, command).await {
+                        warn!("Couldn't respond to reset_guild_shell: {}", e);
                     }
+                    "".into()
                 }
-                /*
-                "load_settings" => {
-                    let settings: &str = command.data.options.get(0).expect("Settings must be present").value.as_ref().expect("Must have value").as_str().expect("Must be string");
-                    let guild_id = command.guild_id.expect("Command must be used in a guild");
-                    match serde_json::from_str(settings) {
-                        Ok(config) => {
-                            let mut data = ctx.data.write().await;
-                            let shells = data.get_mut::<GuildShells>().unwrap();
-                            shells.get_mut(&guild_id).expect("guild shell must exist").config = config;
-                            save_shells(data.get::<GuildShells>().unwrap(), &*data.get::<BaseConfigData>().unwrap().shell_config_file);
-                            println!("loaded successfully!!")
-                        }
-                        Err(e) => println!("Couldnt parse :( {}", e)
+                "load_settings" | settings_io::LOAD_FROM_MESSAGE_COMMAND => {
+                    if let Err(e) = settings_io::preview_settings(&ctx, command).await {
+                        warn!("Couldn't respond to load_settings: {}", e);
                     }
-                    "done (maybe, idk)".into()
+                    "".into()
                 }
 
-                 */
-
-
                 "dump_settings" => {
-                    let data = ctx.data.read().await;
-                    let guild_id = command.guild_id.unwrap();
-                    let shell = data.get::<GuildShells>().unwrap().get(&guild_id).expect("Guild shell must exist");
-
-                    let (send, rc) = oneshot::channel();
-                    shell.channel.send(ShellEvent::GetConfig(send)).await;
-                    let config = rc.await.expect("Failed to retrieve guild config");
-
-                    match serde_json::to_string(&config) {
-                        Ok(res) => format!("```json\n{}```", res),
-                        Err(e) => format!("```Failed to convert config into JSON! This should never happen. Error: {}```", e)
+                    if let Err(e) = settings_io::dump_settings(&ctx, command).await {
+                        warn!("Couldn't respond to dump_settings: {}", e);
                     }
+                    "".into()
                 }
 
                 /*"config" => {
@@ -255,7 +275,7 @@ impl EventHandler for Handler {
                 _ => { "".into() }
             };
 
-            if content != "" {
+            if !content.is_empty() {
                 command.create_interaction_response(&ctx.http, |response| {
                     response
                         .kind(InteractionResponseType::ChannelMessageWithSource)
@@ -266,108 +286,509 @@ impl EventHandler for Handler {
         }
 
         // All shells handle interaction
-        let data = ctx.data.write().await;
-
         if let Some(guild_id) = {
             match &interaction {
                 Interaction::Ping(_ping) => { return; }
                 Interaction::ApplicationCommand(cmd) => { cmd.guild_id }
                 Interaction::MessageComponent(cmp) => { cmp.guild_id }
+                Interaction::Autocomplete(_) => { return; }
             }
         } {
-            let shell = data.get::<GuildShells>().unwrap().get(&guild_id).expect("nonexistent guild smh");
-            shell.channel.send(ShellEvent::NewInteraction(ctx.clone(), interaction)).await;
-        } else {
-            match &interaction {
-                Interaction::ApplicationCommand(cmd) => {
-                    cmd.create_interaction_response(&ctx, |resp| {
-                        resp.interaction_response_data(|data| {
-                            data.create_embed(|e| {
-                                e.title("Error").description("All commands have to be used in a guild").color(0xff0000)
-                            })
-                        })
-                    }).await;
-                }
-                _ => {}
-            };
+            send_to_shell(&ctx, guild_id, ShellEvent::NewInteraction(ctx.clone(), interaction)).await;
+        } else if let Interaction::ApplicationCommand(cmd) = &interaction {
+            if let Err(e) = cmd.create_interaction_response(&ctx, |resp| {
+                resp.interaction_response_data(|data| {
+                    data.create_embed(|e| {
+                        e.title("Error").description("All commands have to be used in a guild").color(0xff0000)
+                    })
+                })
+            }).await {
+                warn!("Couldn't reject a command outside of a guild: {}", e);
+            }
         }
     }
     async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: bool) {
-        println!("New guild! Id: {}, is new: {}", guild.id, _is_new);
-        GuildShell::initialize(&ctx, GuildConfig::new(guild.id)).await;
+        info!(guild_id = %guild.id, is_new = _is_new, "Guild available");
+        GuildShell::initialize(&ctx, stored_config(&ctx, guild.id).await).await;
+    }
+    async fn guild_delete(&self, ctx: Context, incomplete: GuildUnavailable, _full: Option<Guild>) {
+        let guild_id = incomplete.id;
+        if incomplete.unavailable {
+            // An outage, the guild comes back with a guild_create
+            warn!(%guild_id, "Guild became unavailable");
+            return;
+        }
+
+        info!(%guild_id, "Removed from guild");
+        let config = match stop_shell(&ctx, guild_id).await {
+            Some(config) => config,
+            None => stored_config(&ctx, guild_id).await
+        };
+        archive_config(&ctx, config).await;
     }
     async fn message(&self, ctx: Context, msg: Message) {
         if msg.author.bot {
             return;
         }
 
-        let mut data = ctx.data.write().await;
-        println!("{} said {}...", msg.author.name, "something");
-        if let Some(guild_id) = &msg.guild_id {
-            if let Some(shell) = data.get_mut::<GuildShells>().unwrap().get_mut(guild_id) {
-                shell.channel.send(ShellEvent::NewMessage(ctx.clone(), msg)).await;
-            } else {
-                println!("Guild {} has no shell", guild_id)
-            }
+        trace!(guild_id = ?msg.guild_id, user_id = %msg.author.id, content = %msg.content, "Message received");
+        if let Some(guild_id) = msg.guild_id {
+            send_to_shell(&ctx, guild_id, ShellEvent::NewMessage(ctx.clone(), msg)).await;
         } // Else is a DM
     }
+    async fn message_update(&self, ctx: Context, _old_if_available: Option<Message>, _new: Option<Message>, event: MessageUpdateEvent) {
+        if event.author.as_ref().is_some_and(|author| author.bot) {
+            return;
+        }
+
+        trace!(guild_id = ?event.guild_id, message_id = %event.id, content = ?event.content, "Message edited");
+        if let Some(guild_id) = event.guild_id {
+            send_to_shell(&ctx, guild_id, ShellEvent::MessageEdited(ctx.clone(), event)).await;
+        }
+    }
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member_data_if_available: Option<Member>) {
+        if user.bot {
+            return;
+        }
+
+        send_to_shell(&ctx, guild_id, ShellEvent::MemberLeft(user, member_data_if_available)).await;
+    }
+    async fn message_delete(&self, ctx: Context, channel_id: ChannelId, deleted_message_id: MessageId, guild_id: Option<GuildId>) {
+        if let Some(guild_id) = guild_id {
+            send_to_shell(&ctx, guild_id, ShellEvent::MessageDeleted(channel_id, deleted_message_id)).await;
+        }
+    }
+}
+
+
+/// Adds every slash and context menu command of bussy.
+fn create_commands<'a>(commands: &'a mut CreateApplicationCommands, preset_names: &[String]) -> &'a mut CreateApplicationCommands {
+    let mut some_config = GuildConfig::new(0.into());
+    commands
+        .create_application_command(|command| {
+            command.name("ping").description("A ping command")
+        })
+        .create_application_command(|cmd| {
+            cmd.name("config").description("Configure the server settings for bussy")
+                .create_option(|opt| {
+                    opt.name("menu").description("Open the interactive configuration menu").kind(ApplicationCommandOptionType::SubCommand)
+                })
+                .create_option(|opt| {
+                    opt.name("view").description("Show all current values and how they differ from the preset").kind(ApplicationCommandOptionType::SubCommand)
+                })
+                .create_option(|opt| {
+                    opt.name("preset").description("Apply a preset to the whole raid and pressure section").kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|sub| {
+                            sub.name("name").description("Preset to apply").kind(ApplicationCommandOptionType::String).required(true);
+                            for name in preset_names {
+                                sub.add_string_choice(name, name);
+                            }
+                            sub
+                        })
+                })
+                .create_option(|opt| {
+                    opt.name("history").description("Show who changed which setting recently").kind(ApplicationCommandOptionType::SubCommand)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("reset_guild_shell").description("Resets the guild settings to default values, keeping the admin roles")
+                .create_option(|opt| {
+                    opt.name("keep_config").description("Only restart the shell and keep the current settings").kind(ApplicationCommandOptionType::Boolean)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("load_settings").description("Load settings from a JSON or YAML file attached to a message")
+                .create_option(|opt| {
+                    opt.name("message").description("Link or id of the message with the settings file").kind(ApplicationCommandOptionType::String).required(true)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name(settings_io::LOAD_FROM_MESSAGE_COMMAND).kind(ApplicationCommandType::Message)
+        })
+        .create_application_command(|cmd| {
+            cmd.name("dump_settings").description("Dumps the current settings as a file")
+                .create_option(|opt| {
+                    opt.name("format").description("File format, JSON by default").kind(ApplicationCommandOptionType::String)
+                        .add_string_choice("JSON", "json")
+                        .add_string_choice("YAML", "yaml")
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("change").description("Change a setting manually");
+            for field in some_config.get_configurable_fields() {
+                field.add_slash_command_subcommand(cmd);
+            }
+            cmd
+        })
+        .create_application_command(|cmd| {
+            cmd.name("setup").description("Get help setting up Bussy for best experience")
+        })
+        .create_application_command(|cmd| {
+            cmd.name("case").description("Look up or edit a moderation case")
+                .create_option(|opt| {
+                    opt.name("view").description("Show a case").kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|sub| sub.name("number").description("Case number").kind(ApplicationCommandOptionType::Integer).required(true))
+                })
+                .create_option(|opt| {
+                    opt.name("reason").description("Change the reason of a case").kind(ApplicationCommandOptionType::SubCommand)
+                        .create_sub_option(|sub| sub.name("number").description("Case number").kind(ApplicationCommandOptionType::Integer).required(true))
+                        .create_sub_option(|sub| sub.name("reason").description("New reason").kind(ApplicationCommandOptionType::String).required(true))
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("whois").description("Show what bussy remembers about a member")
+                .create_option(|opt| {
+                    opt.name("user").description("Member to look up").kind(ApplicationCommandOptionType::User).required(true)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name(user_records::WHOIS_USER_COMMAND).kind(ApplicationCommandType::User)
+        })
+        .create_application_command(|cmd| {
+            cmd.name("cases").description("List moderation cases, newest first")
+                .create_option(|opt| {
+                    opt.name("user").description("Only cases of this member").kind(ApplicationCommandOptionType::User)
+                })
+                .create_option(|opt| {
+                    opt.name("since").description("Only cases since this date, like 2024-05-01").kind(ApplicationCommandOptionType::String)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("warn").description("Warn a member, who is told the reason")
+                .create_option(|opt| {
+                    opt.name("user").description("Member to warn").kind(ApplicationCommandOptionType::User).required(true)
+                })
+                .create_option(|opt| {
+                    opt.name("reason").description("Sent to the member and kept in the case").kind(ApplicationCommandOptionType::String).required(true)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("silence").description("Silence a member like bussy does with spammers")
+                .create_option(|opt| {
+                    opt.name("user").description("Member to silence").kind(ApplicationCommandOptionType::User).required(true)
+                })
+                .create_option(|opt| {
+                    opt.name("duration").description("Lift it automatically after this long, like 30m, 12h or 1d").kind(ApplicationCommandOptionType::String)
+                })
+                .create_option(|opt| {
+                    opt.name("reason").description("Kept in the case").kind(ApplicationCommandOptionType::String)
+                })
+                .create_option(|opt| {
+                    opt.name("purge_messages").description("Delete their recent messages, yes by default").kind(ApplicationCommandOptionType::Boolean)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("unsilence").description("Lift a member's silence")
+                .create_option(|opt| {
+                    opt.name("user").description("Member to unsilence").kind(ApplicationCommandOptionType::User).required(true)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("purge").description("Delete matching messages among the latest ones in this channel")
+                .create_option(|opt| {
+                    opt.name("count").description("How many of the latest messages to look through, up to 1000").kind(ApplicationCommandOptionType::Integer).required(true)
+                })
+                .create_option(|opt| {
+                    opt.name("user").description("Only messages from this member").kind(ApplicationCommandOptionType::User)
+                })
+                .create_option(|opt| {
+                    opt.name("contains").description("Only messages containing this text").kind(ApplicationCommandOptionType::String)
+                })
+                .create_option(|opt| {
+                    opt.name("has").description("Only messages with links or attachments").kind(ApplicationCommandOptionType::String)
+                        .add_string_choice("Links", "links")
+                        .add_string_choice("Attachments", "attachments")
+                })
+                .create_option(|opt| {
+                    opt.name("new_members").description("Only messages from members who joined within the last day").kind(ApplicationCommandOptionType::Boolean)
+                })
+                .create_option(|opt| {
+                    opt.name("since").description("Only messages after the message with this id").kind(ApplicationCommandOptionType::String)
+                })
+        })
+        .create_application_command(|cmd| {
+            cmd.name("note").description("Add a note to a member's profile, only moderators see it")
+                .create_option(|opt| {
+                    opt.name("user").description("Member the note is about").kind(ApplicationCommandOptionType::User).required(true)
+                })
+                .create_option(|opt| {
+                    opt.name("text").description("The note").kind(ApplicationCommandOptionType::String).required(true)
+                })
+        })
+}
+
+/// Overwrites the commands of `dev_guild`, or the global commands if there is none. Global commands
+/// can take up to an hour to update, guild commands update instantly.
+async fn register_commands(http: &Http, preset_names: &[String], dev_guild: Option<GuildId>) -> Result<Vec<ApplicationCommand>, SerenityError> {
+    match dev_guild {
+        Some(guild_id) => guild_id.set_application_commands(http, |commands| create_commands(commands, preset_names)).await,
+        None => ApplicationCommand::set_global_application_commands(http, |commands| create_commands(commands, preset_names)).await
+    }
 }
 
+/// Passes the event to the guild's shell. The sender is cloned so the TypeMap isn't locked while the
+/// shell's channel is full, the shell itself may be waiting on the lock.
+async fn send_to_shell(ctx: &Context, guild_id: GuildId, event: ShellEvent) {
+    let channel = ctx.data.read().await.get::<GuildShells>().unwrap().get(&guild_id).map(|shell| shell.channel.clone());
+    match channel {
+        Some(channel) => if let Err(e) = channel.send(event).await {
+            warn!(%guild_id, "Shell stopped listening: {}", e);
+        },
+        None => warn!(%guild_id, "Guild has no shell")
+    }
+}
 
-async fn load_shells(ctx: &mut Context) {
-    let filename = ctx.data.read().await.get::<BaseConfigData>().unwrap().shell_config_file.clone();
-    let mut file = std::fs::File::open(filename).expect("File could not be opened");
+/// Asks the guild's shell for a copy of its current config.
+async fn request_config(ctx: &Context, guild_id: &GuildId) -> Option<GuildConfig> {
+    let channel = ctx.data.read().await.get::<GuildShells>().unwrap().get(guild_id)?.channel.clone();
+    let (send, rc) = oneshot::channel();
+    channel.send(ShellEvent::GetConfig(send)).await.ok()?;
+    rc.await.ok()
+}
 
-    let mut data = String::new();
-    file.read_to_string(&mut data).expect("File couldn't be read into string");
-    let deserialized: HashMap<GuildId, GuildConfig> = serde_yaml::from_str(&*data).expect("File could not be deserialized");
-    for (_id, config) in deserialized {
-        GuildShell::initialize(ctx, config).await;
+/// Commands that change or expose the guild configuration and are reserved for admins.
+const ADMIN_COMMANDS: [&str; 15] = ["config", "change", "dump_settings", "reset_guild_shell", "load_settings", settings_io::LOAD_FROM_MESSAGE_COMMAND, "case", "cases",
+    "whois", user_records::WHOIS_USER_COMMAND, "warn", "note", "silence", "unsilence", "purge"];
+
+/// Checks whether the invoker may use the interaction. Admin commands and all components require
+/// one of the configured admin roles or the Manage Guild permission.
+async fn is_authorized(ctx: &Context, interaction: &Interaction) -> bool {
+    let (guild_id, member) = match interaction {
+        Interaction::ApplicationCommand(cmd) if ADMIN_COMMANDS.contains(&cmd.data.name.as_str()) => (cmd.guild_id, &cmd.member),
+        Interaction::MessageComponent(cmp) => (cmp.guild_id, &cmp.member),
+        _ => return true
+    };
+
+    match (guild_id, member) {
+        (Some(guild_id), Some(member)) => is_admin(ctx, guild_id, member).await,
+        _ => true  // Not in a guild, the command is rejected further down anyway
     }
+}
 
-    save_shells(&mut ctx.data).await;  // Instant check whether the file can also be saved.
+/// Whether the member may use admin commands in the guild. Doesn't wait on the shell, so a busy or
+/// stuck shell can't lock admins out of commands like `/reset_guild_shell`.
+async fn is_admin(ctx: &Context, guild_id: GuildId, member: &Member) -> bool {
+    let admin_roles = ctx.data.read().await.get::<GuildShells>().unwrap().get(&guild_id).map(|shell| shell.admin_roles.clone());
+    match admin_roles {
+        Some(admin_roles) => guild_shell::is_admin(member, &admin_roles.read().unwrap()),
+        None => stored_config(ctx, guild_id).await.is_admin(member)
+    }
 }
 
+async fn deny_interaction(ctx: &Context, interaction: &Interaction) {
+    let text = "You need an admin role or the Manage Server permission to do this.";
+    let res = match interaction {
+        Interaction::ApplicationCommand(cmd) => {
+            cmd.create_interaction_response(&ctx, |resp| {
+                resp.interaction_response_data(|data| {
+                    data.content(text).flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+            }).await
+        }
+        Interaction::MessageComponent(cmp) => {
+            cmp.create_interaction_response(&ctx, |resp| {
+                resp.interaction_response_data(|data| {
+                    data.content(text).flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+            }).await
+        }
+        _ => Ok(())
+    };
+    if let Err(e) = res {
+        warn!("Couldn't deny interaction: {}", e);
+    }
+}
 
-async fn save_shells(dat: &mut Arc<RwLock<TypeMap>>) {
-    let mut data = dat.write().await;
-    let filename = data.get::<BaseConfigData>().unwrap().shell_config_file.clone();
-    let shells = data.get_mut::<GuildShells>().unwrap();
+/// Posts problems that need the bot owner's attention to the debug channel.
+async fn report_to_debug_channel(ctx: &Context, channel: ChannelId, title: &str, lines: &[String]) {
+    let mut description = lines.join("\n");
+    if description.len() > 4000 {
+        let mut end = 4000;
+        while !description.is_char_boundary(end) {
+            end -= 1;
+        }
+        description.truncate(end);
+        description.push_str("\n…");
+    }
+
+    if let Err(e) = channel.send_message(&ctx, |msg| {
+        msg.add_embed(|e| e.title(title).description(description).color(0xff0000))
+    }).await {
+        error!("Couldn't report to the debug channel: {}", e);
+    }
+}
+
+/// Starts the stored shells of the `current` guilds. Configs of other guilds are archived, and deleted
+/// once they have been archived for longer than `removed_guild_days`.
+async fn load_shells(ctx: &mut Context, current: &[GuildId]) {
+    let (filename, debug_channel_id, store) = {
+        let data = ctx.data.read().await;
+        let base = data.get::<BaseConfigData>().unwrap();
+        (base.shell_config_file.clone(), base.debug_channel_id, data.get::<ShellStorage>().unwrap().clone())
+    };
+
+    let (deserialized, mut problems) = store.load_all();
+    for (id, mut config) in deserialized {
+        if current.contains(&id) {
+            GuildShell::initialize(ctx, config).await;
+            continue;
+        }
+        match config.removed_at {
+            Some(_) => debug!(guild_id = %id, "Config is archived"),
+            None => {
+                // Removed while bussy was offline
+                config.removed_at = Some(Utc::now());
+                if let Err(e) = storage::blocking(&store, move |store| store.save(&config)).await {
+                    problems.push(format!("The config of removed guild {} could not be archived: {}", id, e));
+                }
+                info!(guild_id = %id, "Archived the config of a guild bussy is no longer in");
+            }
+        }
+    }
+    problems.extend(delete_expired_configs(&ctx.data).await);
+    if !problems.is_empty() {
+        report_to_debug_channel(ctx, debug_channel_id, &format!("Problems loading {}", filename), &problems).await;
+    }
+}
+
+/// Deletes the configs that have been archived for longer than `removed_guild_days`. Guilds with a
+/// running shell are skipped, bussy was added back to them. Returns what could not be deleted.
+async fn delete_expired_configs(data: &Arc<RwLock<TypeMap>>) -> Vec<String> {
+    let (removed_guild_days, store, running) = {
+        let data = data.read().await;
+        let running: Vec<GuildId> = data.get::<GuildShells>().unwrap().keys().copied().collect();
+        (data.get::<BaseConfigData>().unwrap().removed_guild_days, data.get::<ShellStorage>().unwrap().clone(), running)
+    };
+
+    let cutoff = Utc::now() - chrono::Duration::days(removed_guild_days as i64);
+    let configs = match storage::blocking(&store, |store| Ok(store.load_all().0)).await {
+        Ok(configs) => configs,
+        Err(e) => return vec![format!("Couldn't look for expired configs: {}", e)]
+    };
+
+    let mut problems = Vec::new();
+    for (id, config) in configs {
+        match config.removed_at {
+            Some(removed_at) if removed_at <= cutoff && !running.contains(&id) => {
+                match storage::blocking(&store, move |store| store.delete(id)).await {
+                    Ok(()) => info!(guild_id = %id, "Deleted the config of a guild removed on {}", removed_at),
+                    Err(e) => problems.push(format!("The config of removed guild {} could not be deleted: {}", id, e))
+                }
+            }
+            _ => {}
+        }
+    }
+    problems
+}
+
+/// Runs `delete_expired_configs` every `EXPIRED_CONFIG_INTERVAL` for as long as bussy runs, on
+/// top of the run in `load_shells` on every start.
+async fn delete_expired_configs_periodically(data: Arc<RwLock<TypeMap>>) {
+    let mut interval = tokio::time::interval(EXPIRED_CONFIG_INTERVAL);
+    // The first tick is immediate, `load_shells` covers the start
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for problem in delete_expired_configs(&data).await {
+            error!("{}", problem);
+        }
+    }
+}
+
+/// The saved config for the guild, or the defaults if it has none.
+async fn stored_config(ctx: &Context, guild_id: GuildId) -> GuildConfig {
+    let store = ctx.data.read().await.get::<ShellStorage>().unwrap().clone();
+    match store.load(guild_id) {
+        Ok(Some(config)) => config,
+        Ok(None) => GuildConfig::new(guild_id),
+        Err(e) => {
+            warn!(%guild_id, "Couldn't load the config, using defaults: {}", e);
+            GuildConfig::new(guild_id)
+        }
+    }
+}
 
-    let mut shell_configs: HashMap<GuildId, GuildConfig> = Default::default();
 
-    for (id, shell) in shells {  // TODO: Rewrite to collect configs concurrently
+async fn save_shells(dat: &mut Arc<RwLock<TypeMap>>) {
+    // The lock is released before waiting on the shells, which may be waiting on it themselves
+    let (store, channels) = {
+        let data = dat.read().await;
+        let channels: Vec<(GuildId, mpsc::Sender<ShellEvent>)> = data.get::<GuildShells>().unwrap().iter()
+            .map(|(id, shell)| (*id, shell.channel.clone()))
+            .collect();
+        (data.get::<ShellStorage>().unwrap().clone(), channels)
+    };
+
+    let requests = channels.into_iter().map(|(id, channel)| async move {
         let (sender, receiver) = oneshot::channel();
-        shell.channel.send(ShellEvent::GetConfig(sender)).await;
-        if let Ok(config) = receiver.await {
-            shell_configs.insert(id.clone(), config);
-        } else {
-            println!("Error retrieving the config for guild {}", id);
+        if channel.send(ShellEvent::GetConfig(sender)).await.is_err() {
+            return Err(id);
+        }
+        receiver.await.map_err(|_| id)
+    });
+
+    let mut shell_configs: Vec<GuildConfig> = Default::default();
+    for result in join_all(requests).await {
+        match result {
+            Ok(config) => shell_configs.push(config),
+            Err(id) => warn!(guild_id = %id, "Couldn't retrieve the config")
         }
     }
 
-    let serialized = serde_yaml::to_string(&shell_configs).expect("Can't serialize shells");
+    if let Err(e) = storage::blocking(&store, move |store| store.save_all(&shell_configs)).await {
+        error!("Couldn't save shells: {}", e);
+    }
+}
+
+/// Closes the guild's shell channel and waits for its task to finish, which saves its runtime state.
+/// Returns the shell's last config, including everything queued before the channel closed, or `None`
+/// if no shell was running or it crashed.
+async fn stop_shell(ctx: &Context, guild_id: GuildId) -> Option<GuildConfig> {
+    let shell = ctx.data.write().await.get_mut::<GuildShells>().unwrap().remove(&guild_id)?;
+    drop(shell.channel);
+    match shell.handle.await {
+        Ok(config) => Some(config),
+        Err(e) => {
+            error!(%guild_id, "The shell task did not shut down cleanly: {}", e);
+            None
+        }
+    }
+}
 
-    let mut file = std::fs::File::create(filename).expect("Can't open file ");
-    file.write(serialized.as_ref()).expect("File couldn't be written into");
+/// Keeps the config of a guild bussy was removed from until `load_shells` deletes it.
+async fn archive_config(ctx: &Context, mut config: GuildConfig) {
+    let store = ctx.data.read().await.get::<ShellStorage>().unwrap().clone();
+    config.removed_at = Some(Utc::now());
+    let guild_id = config.guild_id;
+    match storage::blocking(&store, move |store| store.save(&config)).await {
+        Ok(()) => info!(%guild_id, "Archived the config"),
+        Err(e) => error!(%guild_id, "Couldn't archive the config: {}", e)
+    }
+}
+
+/// Closes every shell's channel and waits for its task to finish, which saves its runtime state.
+async fn shutdown_shells(dat: &Arc<RwLock<TypeMap>>) {
+    let shells: Vec<ShellContact> = dat.write().await.get_mut::<GuildShells>().unwrap().drain().map(|(_, shell)| shell).collect();
+    for shell in shells {
+        drop(shell.channel);
+        if let Err(e) = shell.handle.await {
+            error!("A shell task did not shut down cleanly: {}", e);
+        }
+    }
 }
 
 async fn run(config: BaseConfigData) {
     let framework = StandardFramework::new()
-        .configure(|c| c.prefix("~")) // set the bot's prefix to "~"
+        .configure(|c| c.prefix(&config.command_prefix))
         .group(&GENERAL_GROUP);
 
-    let intents = GatewayIntents::all();
-    // intents.guild_members();
-    // intents.direct_messages();
-
 
     // Build our client.
     let mut client = Client::builder(&config.bot_token)
         .event_handler(Handler)
         .framework(framework)
         .application_id(config.application_id)
-        .intents(intents)
+        .intents(config.intents)
         .await
         .expect("Error creating client");
 
@@ -375,31 +796,38 @@ async fn run(config: BaseConfigData) {
     {
         let mut data = client.data.write().await;
         data.insert::<GuildShells>(Default::default());
+        // Custom presets live next to the shells file
+        data.insert::<ConfigPresets>(Arc::new(presets::load_presets(&presets::presets_path(&config.shell_config_file))));
+        data.insert::<ShellStorage>(storage::open_store(&config.shell_config_file).expect("Shell storage could not be opened"));
         data.insert::<BaseConfigData>(config);
         data.insert::<LogData>(LogData::default());
+        data.insert::<PendingSettings>(Default::default());
     }
 
 
+    tokio::spawn(delete_expired_configs_periodically(client.data.clone()));
+
     let shard_manager = client.shard_manager.clone();
     tokio::spawn(async move {
         tokio::signal::ctrl_c().await.expect("Could not register ctrl+c handler");
-        println!("SHUTTING DOWN GRACEFULLY");
+        info!("Shutting down gracefully");
         shard_manager.lock().await.shutdown_all().await;
     });
 
 
     if let Err(why) = client.start().await {
-        println!("Cleint exit with error: {}", why);
+        error!("Client exited with error: {}", why);
     }
 
     {
         save_shells(&mut client.data).await;
+        shutdown_shells(&client.data).await;
     }
 }
 
 #[command]
 async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
-    println!("Pinged!");
+    debug!("Pinged!");
     let channel = msg.channel(ctx).await.unwrap();
     if let serenity::model::channel::Channel::Guild(ch) = channel {
         ch.say(&ctx, "Pong").await.expect("Couldn't respond to ping!");
@@ -407,43 +835,116 @@ async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
+/// Restarts the guild's shell with its current config. Admins only, like the slash commands.
 #[command]
 async fn recreate_shell(ctx: &Context, msg: &Message) -> CommandResult {
-    _recreate_shell(&ctx, &msg.guild_id.expect("Guild id")).await
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(())  // A DM
+    };
+    let mut member = msg.member(ctx).await?;
+    // Only interactions come with the member's permissions
+    member.permissions = member.permissions(ctx).await.ok();
+    if !is_admin(ctx, guild_id, &member).await {
+        msg.reply(ctx, "You need an admin role or the Manage Server permission to do this.").await?;
+        return Ok(());
+    }
+
+    let outcome = match _recreate_shell(ctx, &guild_id, msg.author.id, true).await {
+        Ok(outcome) => outcome,
+        Err(e) => format!("The shell could not be recreated: {}", e)
+    };
+    msg.reply(ctx, outcome).await?;
+    Ok(())
 }
 
-async fn _recreate_shell(_ctx: &Context, _guild_id: &GuildId) -> CommandResult {
-    todo!();
-    /*
-    println!("Got recreate shell command");
-    let mut data = ctx.data.write().await;
-    let config_file = &data.get::<BaseConfigData>().unwrap().shell_config_file.clone();
-    let shells = data.get_mut::<GuildShells>().unwrap();
-    shells.remove(guild_id);
+/// Recreates the shell for `/reset_guild_shell`. Waiting for the old shell can take longer than
+/// Discord waits for a response, so the outcome is sent as a follow up.
+async fn reset_guild_shell(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+    command.create_interaction_response(&ctx, |resp| {
+        resp.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+    }).await?;
+
+    let keep_config = moderation::option(&command.data.options, "keep_config").and_then(|v| v.as_bool()).unwrap_or(false);
+    let outcome = match _recreate_shell(ctx, &command.guild_id.expect("Must be used in a guild"), command.user.id, keep_config).await {
+        Ok(outcome) => outcome,
+        Err(e) => format!("The shell could not be recreated: {}", e)
+    };
+    command.create_followup_message(&ctx, |msg| msg.content(outcome)).await?;
+    Ok(())
+}
 
-    GuildShell::initialize(ctx, GuildConfig::new(guild_id.clone())).await;
+/// Stops the guild's shell and starts a fresh one, with the previous config or the defaults,
+/// then saves the new config. Returns a description of the outcome.
+async fn _recreate_shell(ctx: &Context, guild_id: &GuildId, actor: UserId, keep_config: bool) -> Result<String, String> {
+    info!(%guild_id, keep_config, "Recreating shell");
+    let previous = stop_shell(ctx, *guild_id).await;
+    let was_running = previous.is_some();
+    let previous = match previous {
+        Some(config) => config,
+        None => stored_config(ctx, *guild_id).await
+    };
+    let config = if keep_config { previous } else { previous.reset(actor) };
+
+    GuildShell::initialize(ctx, config).await;
+    let config = request_config(ctx, guild_id).await.ok_or("The new shell did not start")?;
+    let store = ctx.data.read().await.get::<ShellStorage>().unwrap().clone();
+    storage::blocking(&store, move |store| store.save(&config)).await?;
+
+    let settings = if keep_config { "its current settings" } else { "the default settings" };
+    Ok(if was_running {
+        format!("The shell was restarted with {}.", settings)
+    } else {
+        format!("No shell was running, a new one was started with {}.", settings)
+    })
+}
 
-    save_shells(&mut ctx.data).await;
-    Ok(())
+/// Registers the commands over plain HTTP, so they can be updated without starting the bot.
+async fn register_only(config: BaseConfigData) {
+    let http = Http::new_with_token_application_id(&config.bot_token, config.application_id);
+    let preset_names: Vec<String> = presets::load_presets(&presets::presets_path(&config.shell_config_file)).into_keys().collect();
+
+    match register_commands(&http, &preset_names, config.dev_guild).await {
+        Ok(commands) => info!("Registered {} commands", commands.len()),
+        Err(e) => {
+            error!("Couldn't register commands: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-     */
+/// Loads every stored config the way the bot would on startup and reports what it found.
+fn check_data(location: &str) {
+    // Read only, a check must not move or rewrite the files it checks
+    let (configs, problems) = match storage::check_store(location) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Couldn't open {}: {}", location, e);
+            std::process::exit(1);
+        }
+    };
+    info!("Loaded configs for {} guilds from {}", configs.len(), location);
+    let archived = configs.values().filter(|config| config.removed_at.is_some()).count();
+    if archived > 0 {
+        info!("{} of them belong to guilds bussy was removed from", archived);
+    }
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("BOT_TOKEN").expect("Bot token in .env");
+    let cli = Cli::parse();
+    cli.logging.init();
 
-    // The Application Id is usually the Bot User Id.
-    let application_id: u64 = env::var("APPLICATION_ID").expect("Application ID in .env").parse::<u64>().expect("Application Id to be a u64");
-
-
-    let debug_channel_id = env::var("DEBUG_CHANNEL_ID")
-        .expect("DEBUG_CHANNEL_ID in environment").parse::<ChannelId>()
-        .expect("Debug channel ID to be a valid channel id");
-
-    let config = BaseConfigData { debug_channel_id: debug_channel_id, bot_token: token.to_string(), application_id, shell_config_file: "shells.yml".to_string() };
-
-    run(config).await;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(cli.run.into_base_config()).await,
+        Command::RegisterCommands => register_only(cli.run.into_base_config()).await,
+        Command::CheckData => check_data(&cli.run.data),
+    }
 }
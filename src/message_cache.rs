@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+
+use serenity::client::Context;
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::prelude::SerenityError;
+
+use crate::archive::MessageSnapshot;
+use crate::error_handling::Loggable;
+use crate::guild_shell::GuildShell;
+use crate::moderation_log::{truncate, LogEntry};
+
+/// How many recent messages each guild remembers for the edit and delete logs.
+const MESSAGE_CACHE_SIZE: usize = 500;
+/// Message contents are cut to this length in the log channel.
+const LOGGED_CONTENT_LENGTH: usize = 1000;
+
+/// The latest messages of a guild, oldest first. Not persisted.
+#[derive(Debug, Default)]
+pub struct MessageCache {
+    messages: VecDeque<MessageSnapshot>,
+}
+
+impl MessageCache {
+    pub fn insert(&mut self, snapshot: MessageSnapshot) {
+        self.messages.push_back(snapshot);
+        while self.messages.len() > MESSAGE_CACHE_SIZE {
+            self.messages.pop_front();
+        }
+    }
+
+    pub fn get(&self, message_id: MessageId) -> Option<&MessageSnapshot> {
+        self.messages.iter().rev().find(|snapshot| snapshot.id == message_id)
+    }
+
+    pub fn get_mut(&mut self, message_id: MessageId) -> Option<&mut MessageSnapshot> {
+        self.messages.iter_mut().rev().find(|snapshot| snapshot.id == message_id)
+    }
+
+    pub fn remove(&mut self, message_id: MessageId) -> Option<MessageSnapshot> {
+        let position = self.messages.iter().rposition(|snapshot| snapshot.id == message_id)?;
+        self.messages.remove(position)
+    }
+}
+
+fn logged_content(content: &str) -> String {
+    let mut content = if content.is_empty() { "*no text*".to_string() } else { content.to_string() };
+    truncate(&mut content, LOGGED_CONTENT_LENGTH);
+    content
+}
+
+impl GuildShell {
+    /// Logs the change and re-scores the message, so benign messages can't be edited into spam.
+    pub async fn message_edited(&mut self, ctx: &Context, event: &MessageUpdateEvent) -> Result<(), SerenityError> {
+        // Updates without content are embeds of links loading
+        let content = match &event.content {
+            Some(content) => content,
+            None => return Ok(())
+        };
+        let cached = self.message_cache.get(event.id).cloned();
+        let author = match event.author.as_ref().map(|author| author.id).or(cached.as_ref().map(|snapshot| snapshot.author)) {
+            Some(author) => author,
+            None => return Ok(())
+        };
+        if cached.as_ref().is_some_and(|snapshot| &snapshot.content == content) {
+            return Ok(());
+        }
+        if self.ensure_member_shell(ctx, author).await.is_err() {
+            return Err(serenity::Error::Other("Member shell couldn't be ensured :("));
+        }
+
+        let embeds = event.embeds.as_ref().map(Vec::len).or(cached.as_ref().map(|snapshot| snapshot.embeds.len())).unwrap_or(0);
+        let mentions = event.mentions.as_ref().map(|mentions| mentions.iter().map(|user| user.id).collect())
+            .or(cached.as_ref().map(|snapshot| snapshot.mentions.clone())).unwrap_or_default();
+        let new_pressure = self.calculate_pressure(content, embeds, mentions.len());
+        // The message was scored when it was sent, only what the edit adds counts
+        let old_pressure = match &cached {
+            Some(snapshot) => self.calculate_pressure(&snapshot.content, snapshot.embeds.len(), snapshot.mentions.len()),
+            // At least the base pressure was counted for the original
+            None => *self.config.message_pressure
+        };
+
+        if let Some(snapshot) = self.message_cache.get_mut(event.id) {
+            snapshot.content = content.clone();
+            snapshot.mentions = mentions;
+        }
+        let before = cached.map_or("*not cached*".to_string(), |snapshot| logged_content(&snapshot.content));
+        if let Some(shell) = self.active_members.get_mut(&author) {
+            shell.log(LogEntry::MessageEdited { channel: event.channel_id, before, after: logged_content(content) });
+        }
+
+        self.mark_state_dirty();
+        self.add_pressure(ctx, author, (event.id, event.channel_id), (new_pressure - old_pressure).max(0.)).await;
+        Ok(())
+    }
+
+    /// Logs deleted messages that are still cached. Messages bussy deletes itself are removed from the cache first.
+    pub fn message_deleted(&mut self, channel: ChannelId, message_id: MessageId) {
+        if let Some(snapshot) = self.message_cache.remove(message_id) {
+            self.log(LogEntry::MessageDeleted {
+                author: snapshot.author,
+                channel,
+                content: logged_content(&snapshot.content),
+                attachments: snapshot.attachments.len(),
+            });
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::prelude::*;
+use serde_json::Value;
+use serenity::client::Context;
+use serenity::model::interactions::application_command::{ApplicationCommandInteraction, ApplicationCommandInteractionDataOption, ApplicationCommandInteractionDataOptionValue};
+use serenity::model::interactions::InteractionApplicationCommandCallbackDataFlags;
+use serenity::model::user::User;
+use serenity::prelude::SerenityError;
+use tracing::debug;
+
+use crate::cases::{Actor, CaseAction};
+use crate::error_handling::Loggable;
+use crate::guild_shell::GuildShell;
+use crate::moderation_log::LogEntry;
+use crate::user_records::Note;
+
+pub(crate) fn option<'a>(options: &'a [ApplicationCommandInteractionDataOption], name: &str) -> Option<&'a Value> {
+    options.iter().find(|o| o.name == name).and_then(|o| o.value.as_ref())
+}
+
+/// The user picked for a user option, as resolved by Discord.
+pub(crate) fn user_option(options: &[ApplicationCommandInteractionDataOption], name: &str) -> Option<User> {
+    options.iter().find(|o| o.name == name).and_then(|o| o.resolved.as_ref()).and_then(|resolved| match resolved {
+        ApplicationCommandInteractionDataOptionValue::User(user, _) => Some(user.clone()),
+        _ => None
+    })
+}
+
+/// Parses durations like `30m`, `12h` or `1d12h`.
+fn parse_duration(text: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("{} is not a duration like 30m, 12h or 1d12h", text);
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        total += match c {
+            's' => chrono::Duration::seconds(value),
+            'm' => chrono::Duration::minutes(value),
+            'h' => chrono::Duration::hours(value),
+            'd' => chrono::Duration::days(value),
+            'w' => chrono::Duration::weeks(value),
+            _ => return Err(invalid())
+        };
+    }
+    if !number.is_empty() || total <= chrono::Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+async fn reply(ctx: &Context, command: &ApplicationCommandInteraction, content: String) -> Result<(), SerenityError> {
+    command.create_interaction_response(&ctx, |resp| {
+        resp.interaction_response_data(|d| d.content(content).flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL))
+    }).await
+}
+
+impl GuildShell {
+    /// Handles `/warn`. Members who reach the warning limit are silenced.
+    pub(crate) async fn handle_warn(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let user = user_option(&command.data.options, "user");
+        let reason = option(&command.data.options, "reason").and_then(|v| v.as_str());
+        let (user, reason) = match (user, reason) {
+            (Some(user), Some(reason)) => (user, reason.to_string()),
+            _ => return reply(ctx, command, "Pick a member and give a reason.".into()).await
+        };
+
+        let pressure = self.member_pressure(user.id);
+        let number = self.open_case(user.id, Actor::Moderator(command.user.id), CaseAction::Warn, Some(reason.clone()), pressure).await;
+        let record = self.user_records.entry(user.id);
+        record.warnings += 1;
+        let warnings = record.warnings;
+        self.mark_state_dirty();
+
+        let guild_name = self.config.guild_id.name(&ctx).await.unwrap_or_else(|| "the server".into());
+        let delivery = match self.notify_member(ctx, &user, &format!("You were warned in **{}**: {}", guild_name, reason)).await {
+            Some(place) => format!("The warning was delivered {}.", place),
+            None => "The warning could not be delivered.".to_string()
+        };
+        let limit = *self.config.warning_limit;
+        let silence = limit > 0 && warnings >= limit;
+        let mut response = format!("Warned <@{}>, case #{}. {} They have {} warning(s).", user.id, number, delivery, warnings);
+        if silence {
+            response.push_str(&format!(" Silencing them for reaching the limit of {}.", limit));
+        }
+        reply(ctx, command, response).await?;
+
+        if silence {
+            self.silence_member(ctx, &user.id, Actor::Bot, Some(format!("Reached {} warnings", warnings)), None, true).await;
+        }
+        Ok(())
+    }
+
+    /// Handles `/silence`, which goes through the same path as automatic silencing.
+    pub(crate) async fn handle_silence(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let user = match user_option(&command.data.options, "user") {
+            Some(user) => user,
+            None => return reply(ctx, command, "Pick a member to silence.".into()).await
+        };
+        let duration = match option(&command.data.options, "duration").and_then(|v| v.as_str()).map(parse_duration).transpose() {
+            Ok(duration) => duration,
+            Err(e) => return reply(ctx, command, e).await
+        };
+        let reason = option(&command.data.options, "reason").and_then(|v| v.as_str()).map(String::from);
+        let purge_messages = option(&command.data.options, "purge_messages").and_then(|v| v.as_bool()).unwrap_or(true);
+
+        let until = match duration {
+            Some(duration) => format!(" until <t:{}:f>", (Utc::now() + duration).timestamp()),
+            None => String::new()
+        };
+        // Deleting messages can take longer than Discord waits for a response
+        reply(ctx, command, format!("Silencing <@{}>{}.", user.id, until)).await?;
+        self.silence_member(ctx, &user.id, Actor::Moderator(command.user.id), reason, duration, purge_messages).await;
+        Ok(())
+    }
+
+    /// Handles `/unsilence`.
+    pub(crate) async fn handle_unsilence(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let user = match user_option(&command.data.options, "user") {
+            Some(user) => user,
+            None => return reply(ctx, command, "Pick a member to unsilence.".into()).await
+        };
+        let actor = Actor::Moderator(command.user.id);
+        let response = match self.lift_silence(user.id, actor).await {
+            Ok(()) => {
+                let number = self.open_case(user.id, actor, CaseAction::Unsilence, None, None).await;
+                format!("Lifted the silence of <@{}>, case #{}.", user.id, number)
+            }
+            Err(e) => format!("The silence of <@{}> could not be lifted: {}", user.id, e)
+        };
+        reply(ctx, command, response).await
+    }
+
+    /// Handles `/note`.
+    pub(crate) async fn handle_note(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let user = user_option(&command.data.options, "user");
+        let text = option(&command.data.options, "text").and_then(|v| v.as_str());
+        let (user, text) = match (user, text) {
+            (Some(user), Some(text)) => (user, text.to_string()),
+            _ => return reply(ctx, command, "Pick a member and write a note.".into()).await
+        };
+
+        self.user_records.entry(user.id).notes.push(Note { author: command.user.id, text, timestamp: Utc::now() });
+        self.log(LogEntry::NoteAdded { target: user.id, author: command.user.id });
+        self.mark_state_dirty();
+        reply(ctx, command, format!("Added a note to <@{}>'s profile.", user.id)).await
+    }
+
+    /// DMs the member, or mentions them in the silence containment channel if their DMs are closed.
+    /// Returns where the message went.
+    async fn notify_member(&mut self, ctx: &Context, user: &User, text: &str) -> Option<String> {
+        match user.direct_message(&ctx, |m| m.content(text)).await {
+            Ok(_) => return Some("by DM".into()),
+            Err(e) => debug!(user_id = %user.id, "Couldn't DM member: {}", e)
+        }
+
+        let channel = match *self.config.silence_containment_channel {
+            Some(channel) => channel,
+            None => {
+                self.log_error("A message could not be delivered", format!("<@{}> has closed DMs and no silence containment channel is set", user.id));
+                return None;
+            }
+        };
+        match channel.send_message(&ctx, |m| m.content(format!("<@{}> {}", user.id, text))).await {
+            Ok(_) => Some(format!("in <#{}>", channel)),
+            Err(e) => {
+                self.log_error("A message could not be delivered", e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+use chrono::prelude::*;
+use serenity::model::id::{ChannelId, RoleId, UserId};
+use serenity::prelude::TypeMapKey;
+use tracing::{error, info, trace, warn};
+
+use crate::cases::{Actor, CaseAction};
+use crate::config_history::ConfigChange;
+
+/// How many entries are kept until they are dumped, for example while no log channel is set.
+const MAX_LOG_RECORDS: usize = 200;
+
+// Discord limits, see https://discord.com/developers/docs/resources/channel#embed-object-embed-limits
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const EMBEDS_PER_MESSAGE: usize = 10;
+const MESSAGE_EMBED_CHARACTERS: usize = 6000;
+const FILES_PER_MESSAGE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    RoleAssigned { role: RoleId },
+    /// `field` is the name of the config field that is not set.
+    NotConfigured { field: &'static str },
+    JoinedDuringRaid,
+    MemberLeft { user_id: UserId, silenced: bool, during_raid: bool },
+    /// The member rejoined after leaving while silenced or during a raid.
+    Rejoined { left: DateTime<Utc>, silenced: bool, during_raid: bool },
+    SilenceReapplied,
+    PressureExceeded { pressure: f64, limit: f64 },
+    MemberSilenced { user_id: UserId },
+    SilenceResumed { user_id: UserId },
+    SilenceLifted { user_id: UserId, actor: Actor },
+    RaidStarted { raiders: usize },
+    RaidEnded { raiders: usize, started: DateTime<Utc> },
+    ConfigChanged(ConfigChange),
+    PresetApplied { actor: UserId, preset: String },
+    CaseOpened { number: u32, action: CaseAction, target: UserId },
+    CaseUpdated { number: u32, actor: UserId },
+    NoteAdded { target: UserId, author: UserId },
+    /// `authors` counts the deleted messages per author.
+    MessagesPurged { actor: UserId, channel: ChannelId, deleted: usize, authors: Vec<(UserId, usize)> },
+    MessageEdited { channel: ChannelId, before: String, after: String },
+    MessageDeleted { author: UserId, channel: ChannelId, content: String, attachments: usize },
+    Error { message: String },
+}
+
+impl LogEntry {
+    pub fn severity(&self) -> Severity {
+        match self {
+            LogEntry::NotConfigured { .. } | LogEntry::PressureExceeded { .. } | LogEntry::RaidStarted { .. }
+            | LogEntry::Rejoined { .. } => Severity::Warning,
+            LogEntry::Error { .. } => Severity::Error,
+            _ => Severity::Info
+        }
+    }
+
+    /// Embed color in the log channel.
+    pub fn color(&self) -> u32 {
+        match self {
+            LogEntry::MemberSilenced { .. } | LogEntry::SilenceResumed { .. } | LogEntry::PressureExceeded { .. }
+            | LogEntry::SilenceReapplied | LogEntry::Rejoined { .. } => 0xe67e22,
+            LogEntry::RaidStarted { .. } | LogEntry::JoinedDuringRaid => 0xe74c3c,
+            LogEntry::RaidEnded { .. } | LogEntry::RoleAssigned { .. } | LogEntry::SilenceLifted { .. } => 0x2ecc71,
+            LogEntry::ConfigChanged(_) | LogEntry::PresetApplied { .. } | LogEntry::CaseUpdated { .. } | LogEntry::NoteAdded { .. }
+            | LogEntry::MessagesPurged { .. } => 0x3498db,
+            LogEntry::CaseOpened { .. } | LogEntry::MemberLeft { .. } => 0x95a5a6,
+            LogEntry::MessageEdited { .. } | LogEntry::MessageDeleted { .. } => 0x9b59b6,
+            LogEntry::NotConfigured { .. } => 0xf1c40f,
+            LogEntry::Error { .. } => 0x992d22,
+        }
+    }
+}
+
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogEntry::RoleAssigned { role } => write!(f, "Role {} assigned", role),
+            LogEntry::NotConfigured { field } => write!(f, "The {} is not configured", field.replace('_', " ")),
+            LogEntry::JoinedDuringRaid => write!(f, "Joined during raid! No automatic role assignment"),
+            LogEntry::MemberLeft { user_id, silenced, during_raid } => {
+                write!(f, "Member {} left", user_id)?;
+                match (silenced, during_raid) {
+                    (true, true) => write!(f, " while silenced, during a raid they were part of"),
+                    (true, false) => write!(f, " while silenced"),
+                    (false, true) => write!(f, " during a raid they were part of"),
+                    (false, false) => Ok(())
+                }
+            }
+            LogEntry::Rejoined { left, silenced, during_raid } => {
+                let reason = match (silenced, during_raid) {
+                    (true, true) => "while silenced during a raid",
+                    (true, false) => "while silenced",
+                    _ => "during a raid",
+                };
+                write!(f, "Rejoined after leaving {} <t:{}:R>", reason, left.timestamp())
+            }
+            LogEntry::SilenceReapplied => write!(f, "Silence role reapplied"),
+            LogEntry::PressureExceeded { pressure, limit } => write!(f, "Pressure {:.0} surpassed the limit of {:.0}", pressure, limit),
+            LogEntry::MemberSilenced { user_id } => write!(f, "Member {} silenced", user_id),
+            LogEntry::SilenceResumed { user_id } => write!(f, "Resuming the silence of {} interrupted by a restart", user_id),
+            LogEntry::SilenceLifted { user_id, actor } => write!(f, "{} lifted the silence of {}", actor, user_id),
+            LogEntry::RaidStarted { raiders } => write!(f, "Raid started, {} members joined within the trigger timespan", raiders),
+            LogEntry::RaidEnded { raiders, started } => write!(f, "Raid from {} ended with {} raiders", started.format("%H:%M:%S UTC"), raiders),
+            LogEntry::ConfigChanged(change) => write!(f, "{} changed {}: {} → {}", change.actor, change.field, change.old_value, change.new_value),
+            LogEntry::PresetApplied { actor, preset } => write!(f, "{} applied the {} preset", actor, preset),
+            LogEntry::CaseOpened { number, action, target } => write!(f, "Case #{} opened: {} of {}", number, action, target),
+            LogEntry::CaseUpdated { number, actor } => write!(f, "{} updated the reason of case #{}", actor, number),
+            LogEntry::NoteAdded { target, author } => write!(f, "{} added a note to {}", author, target),
+            LogEntry::MessagesPurged { actor, channel, deleted, authors } => {
+                let authors: Vec<String> = authors.iter().map(|(author, count)| format!("{} ({})", author, count)).collect();
+                write!(f, "{} purged {} messages in <#{}> from {}", actor, deleted, channel, authors.join(", "))
+            }
+            LogEntry::MessageEdited { channel, before, after } => write!(f, "Edited a message in <#{}>\n**Before**: {}\n**After**: {}", channel, before, after),
+            LogEntry::MessageDeleted { author, channel, content, attachments } => {
+                write!(f, "Message by {} deleted in <#{}>: {}", author, channel, content)?;
+                if *attachments > 0 {
+                    write!(f, " ({} attachments)", attachments)?;
+                }
+                Ok(())
+            }
+            LogEntry::Error { message } => f.write_str(message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Utc>,
+    pub entry: LogEntry,
+}
+
+/// A text file posted along with the log entries, like the transcript of deleted messages.
+#[derive(Debug, Clone)]
+pub struct LogAttachment {
+    pub filename: String,
+    pub content: String,
+}
+
+/// Log entries waiting to be posted to the log channel, oldest first.
+#[derive(Debug, Default)]
+pub struct LogData {
+    records: VecDeque<LogRecord>,
+    attachments: Vec<LogAttachment>,
+}
+
+impl LogData {
+    pub fn push(&mut self, entry: LogEntry) {
+        match &entry {
+            // Message contents only reach the tracing log at trace level
+            LogEntry::MessageEdited { channel, before, after } => {
+                info!(%channel, before_length = before.len(), after_length = after.len(), "Message edited");
+                trace!("{}", entry);
+            }
+            LogEntry::MessageDeleted { author, channel, content, attachments } => {
+                info!(%author, %channel, content_length = content.len(), attachments, "Message deleted");
+                trace!("{}", entry);
+            }
+            _ => match entry.severity() {
+                Severity::Info => info!("{}", entry),
+                Severity::Warning => warn!("{}", entry),
+                Severity::Error => error!("{}", entry),
+            }
+        }
+        self.records.push_back(LogRecord { timestamp: Utc::now(), entry });
+        while self.records.len() > MAX_LOG_RECORDS {
+            self.records.pop_front();
+        }
+    }
+
+    /// Posts the file with the next flush.
+    pub fn attach(&mut self, filename: String, content: String) {
+        self.attachments.push(LogAttachment { filename, content });
+    }
+
+    pub fn take_attachments(&mut self) -> Vec<LogAttachment> {
+        std::mem::take(&mut self.attachments)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.attachments.is_empty()
+    }
+
+    /// The color of the most severe entry, the latest one if several are equally severe.
+    pub fn color(&self) -> u32 {
+        self.records.iter().max_by_key(|record| record.entry.severity()).map_or(0, |record| record.entry.color())
+    }
+
+    /// Renders the entries into embed descriptions that each fit the description limit.
+    pub fn pages(&self) -> Vec<String> {
+        let mut pages = Vec::new();
+        let mut page = String::new();
+        for record in &self.records {
+            let label = match record.entry.severity() {
+                Severity::Info => "",
+                Severity::Warning => "⚠️ ",
+                Severity::Error => "❌ ",
+            };
+            let mut line = format!("`{}` {}{}", record.timestamp.format("%H:%M:%S"), label, record.entry);
+            truncate(&mut line, EMBED_DESCRIPTION_LIMIT);
+
+            if !page.is_empty() && page.chars().count() + 1 + line.chars().count() > EMBED_DESCRIPTION_LIMIT {
+                pages.push(std::mem::take(&mut page));
+            }
+            if !page.is_empty() {
+                page.push('\n');
+            }
+            page.push_str(&line);
+        }
+        if !page.is_empty() {
+            pages.push(page);
+        }
+        pages
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.attachments.clear();
+    }
+}
+
+/// Cuts `text` down to `max_chars` characters, marking the cut with an ellipsis.
+pub(crate) fn truncate(text: &mut String, max_chars: usize) {
+    if let Some((end, _)) = text.char_indices().nth(max_chars - 1) {
+        text.truncate(end);
+        text.push('…');
+    }
+}
+
+/// One embed of log channel output.
+pub struct LogEmbed {
+    pub title: String,
+    pub description: String,
+    pub color: u32,
+    pub thumbnail: Option<String>,
+    pub fields: Vec<(String, String)>,
+    pub attachments: Vec<LogAttachment>,
+}
+
+impl LogEmbed {
+    fn characters(&self) -> usize {
+        self.title.chars().count() + self.description.chars().count()
+            + self.fields.iter().map(|(name, value)| name.chars().count() + value.chars().count()).sum::<usize>()
+    }
+}
+
+/// Groups embeds into as few messages as Discord's per-message limits allow.
+pub fn batch_embeds(embeds: Vec<LogEmbed>) -> Vec<Vec<LogEmbed>> {
+    let mut messages: Vec<Vec<LogEmbed>> = Vec::new();
+    let mut characters = 0;
+    for embed in embeds {
+        let size = embed.characters();
+        match messages.last_mut() {
+            Some(message) if message.len() < EMBEDS_PER_MESSAGE && characters + size <= MESSAGE_EMBED_CHARACTERS
+                && message.iter().map(|embed| embed.attachments.len()).sum::<usize>() + embed.attachments.len() <= FILES_PER_MESSAGE => {
+                characters += size;
+                message.push(embed);
+            }
+            _ => {
+                characters = size;
+                messages.push(vec![embed]);
+            }
+        }
+    }
+    messages
+}
+
+impl TypeMapKey for LogData {
+    type Value = LogData;
+}
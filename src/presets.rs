@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::GuildId;
+use serenity::prelude::TypeMapKey;
+use tracing::warn;
+
+use crate::guild_shell::GuildConfig;
+
+/// Values for the whole raid and pressure section of a guild config.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigPreset {
+    pub raid_trigger_timespan: u32,
+    pub raid_trigger_new_user_limit: u32,
+    pub raid_autoexpiration: u32,
+    pub max_pressure: f64,
+    pub message_pressure: f64,
+    pub embed_pressure: f64,
+    pub character_pressure: f64,
+    pub newline_pressure: f64,
+    pub unique_ping_pressure: f64,
+    pub pressure_decay_per_second: f64,
+}
+
+impl ConfigPreset {
+    pub fn strict() -> Self {
+        ConfigPreset {
+            raid_trigger_timespan: 120,
+            raid_trigger_new_user_limit: 3,
+            raid_autoexpiration: 1200,
+            max_pressure: 40.0,
+            message_pressure: 10.0,
+            embed_pressure: 10.0,
+            character_pressure: 0.01,
+            newline_pressure: 1.0,
+            unique_ping_pressure: 4.0,
+            pressure_decay_per_second: 6.0,
+        }
+    }
+
+    /// The defaults every new guild starts with.
+    pub fn balanced() -> Self {
+        ConfigPreset {
+            raid_trigger_timespan: 90,
+            raid_trigger_new_user_limit: 5,
+            raid_autoexpiration: 600,
+            max_pressure: 60.0,
+            message_pressure: 10.0,
+            embed_pressure: 8.3,
+            character_pressure: 0.00625,
+            newline_pressure: 0.714,
+            unique_ping_pressure: 2.5,
+            pressure_decay_per_second: 8.0,
+        }
+    }
+
+    pub fn relaxed() -> Self {
+        ConfigPreset {
+            raid_trigger_timespan: 60,
+            raid_trigger_new_user_limit: 10,
+            raid_autoexpiration: 300,
+            max_pressure: 90.0,
+            message_pressure: 8.0,
+            embed_pressure: 6.0,
+            character_pressure: 0.005,
+            newline_pressure: 0.5,
+            unique_ping_pressure: 2.0,
+            pressure_decay_per_second: 10.0,
+        }
+    }
+
+    /// Field names and values in the format `Configurable::set_value` accepts.
+    pub fn values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("raid_trigger_timespan", self.raid_trigger_timespan.to_string()),
+            ("raid_trigger_new_user_limit", self.raid_trigger_new_user_limit.to_string()),
+            ("raid_autoexpiration", self.raid_autoexpiration.to_string()),
+            ("max_pressure", self.max_pressure.to_string()),
+            ("message_pressure", self.message_pressure.to_string()),
+            ("embed_pressure", self.embed_pressure.to_string()),
+            ("character_pressure", self.character_pressure.to_string()),
+            ("newline_pressure", self.newline_pressure.to_string()),
+            ("unique_ping_pressure", self.unique_ping_pressure.to_string()),
+            ("pressure_decay_per_second", self.pressure_decay_per_second.to_string()),
+        ]
+    }
+
+    /// Checks every value the way `/change` would, so applying the preset can't fail halfway.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut config = GuildConfig::new(GuildId(0));
+        for (name, value) in self.values() {
+            let field = config.get_configurable_fields().into_iter().find(|f| f.get_name() == name)
+                .ok_or_else(|| format!("{} is not a known setting", name))?;
+            field.set_value(value).and_then(|_| field.validate(&[], &[])).map_err(|e| format!("{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ConfigPresets;
+
+impl TypeMapKey for ConfigPresets {
+    // Shared with the shells, which must not lock the TypeMap to read them
+    type Value = Arc<BTreeMap<String, ConfigPreset>>;
+}
+
+/// Custom presets live next to the shells file.
+pub fn presets_path(shell_config_file: &str) -> PathBuf {
+    Path::new(shell_config_file).with_file_name("presets.yml")
+}
+
+/// Returns the built in presets, extended or overridden by the ones in `filename` if it exists.
+pub fn load_presets(filename: &Path) -> BTreeMap<String, ConfigPreset> {
+    let mut presets = BTreeMap::new();
+    presets.insert("strict".to_string(), ConfigPreset::strict());
+    presets.insert("balanced".to_string(), ConfigPreset::balanced());
+    presets.insert("relaxed".to_string(), ConfigPreset::relaxed());
+
+    if let Ok(data) = std::fs::read_to_string(filename) {
+        match serde_yaml::from_str::<BTreeMap<String, ConfigPreset>>(&data) {
+            Ok(custom) => {
+                for (name, preset) in custom {
+                    match preset.validate() {
+                        Ok(()) => { presets.insert(name, preset); }
+                        Err(e) => warn!("Preset {} in {} is invalid and was skipped: {}", name, filename.display(), e)
+                    }
+                }
+            }
+            Err(e) => warn!("Presets file {} could not be parsed, using built in presets only: {}", filename.display(), e)
+        }
+    }
+    presets
+}
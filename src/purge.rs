@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::prelude::*;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{MessageId, UserId};
+use serenity::model::interactions::application_command::ApplicationCommandInteraction;
+use serenity::model::interactions::{InteractionApplicationCommandCallbackDataFlags, InteractionResponseType};
+use serenity::prelude::SerenityError;
+
+use crate::archive::MessageSnapshot;
+use crate::error_handling::Loggable;
+use crate::guild_shell::{delete_messages, GuildShell, MessageLocation};
+use crate::moderation::{option, user_option};
+use crate::moderation_log::LogEntry;
+
+/// How many messages `/purge` looks through at most.
+const MAX_PURGE_SCAN: u64 = 1000;
+const MESSAGES_PER_REQUEST: u64 = 100;
+/// Members count as new for this long after joining.
+const NEW_MEMBER_HOURS: i64 = 24;
+/// Discord refuses to bulk delete older messages.
+const BULK_DELETE_MAX_AGE_DAYS: i64 = 14;
+
+/// Which of the scanned messages `/purge` deletes. Unset filters match everything.
+struct PurgeFilter {
+    user: Option<UserId>,
+    contains: Option<String>,
+    links: bool,
+    attachments: bool,
+    new_members: bool,
+    since: Option<MessageId>,
+}
+
+impl PurgeFilter {
+    fn from_command(command: &ApplicationCommandInteraction) -> Result<Self, String> {
+        let options = &command.data.options;
+        let since = option(options, "since").and_then(|v| v.as_str())
+            .map(|id| id.trim().parse::<u64>().map(MessageId).map_err(|_| format!("{} is not a message id", id)))
+            .transpose()?;
+        let has = option(options, "has").and_then(|v| v.as_str());
+        Ok(PurgeFilter {
+            user: user_option(options, "user").map(|user| user.id),
+            contains: option(options, "contains").and_then(|v| v.as_str()).map(str::to_lowercase),
+            links: has == Some("links"),
+            attachments: has == Some("attachments"),
+            new_members: option(options, "new_members").and_then(|v| v.as_bool()).unwrap_or(false),
+            since,
+        })
+    }
+
+    fn matches(&self, message: &Message, new_members: &HashSet<UserId>) -> bool {
+        !message.pinned
+            && self.user.is_none_or(|user| message.author.id == user)
+            && self.contains.as_ref().is_none_or(|text| message.content.to_lowercase().contains(text))
+            && (!self.links || message.content.contains("http://") || message.content.contains("https://"))
+            && (!self.attachments || !message.attachments.is_empty())
+            && (!self.new_members || new_members.contains(&message.author.id))
+    }
+}
+
+impl GuildShell {
+    /// Whether the member joined within `NEW_MEMBER_HOURS`, going by the cache or else bussy's own records.
+    async fn joined_recently(&self, ctx: &Context, user_id: UserId) -> bool {
+        let joined = match ctx.cache.member(self.config.guild_id, user_id).await.and_then(|member| member.joined_at) {
+            Some(joined) => Some(joined),
+            None => self.user_records.get(user_id).and_then(|record| record.joins.last().copied())
+        };
+        joined.is_some_and(|joined| joined + chrono::Duration::hours(NEW_MEMBER_HOURS) > Utc::now())
+    }
+
+    /// Handles `/purge`, which deletes matching messages among the latest `count` in the channel.
+    pub(crate) async fn handle_purge(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        command.create_interaction_response(&ctx, |resp| {
+            resp.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|d| d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL))
+        }).await?;
+
+        let response = match PurgeFilter::from_command(command) {
+            Ok(filter) => self.purge(ctx, command, filter).await,
+            Err(e) => e
+        };
+        command.create_followup_message(&ctx, |msg| {
+            msg.content(response).flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+        }).await?;
+        Ok(())
+    }
+
+    async fn purge(&mut self, ctx: &Context, command: &ApplicationCommandInteraction, filter: PurgeFilter) -> String {
+        let channel = command.channel_id;
+        let count = option(&command.data.options, "count").and_then(|v| v.as_u64()).unwrap_or(MESSAGES_PER_REQUEST).clamp(1, MAX_PURGE_SCAN);
+
+        // Newest first, stopping at the `since` message
+        let mut scanned: Vec<Message> = Vec::new();
+        let mut before: Option<MessageId> = None;
+        while (scanned.len() as u64) < count {
+            let limit = (count - scanned.len() as u64).min(MESSAGES_PER_REQUEST);
+            let page = match channel.messages(&ctx, |r| {
+                match before {
+                    Some(before) => r.before(before).limit(limit),
+                    None => r.limit(limit)
+                }
+            }).await {
+                Ok(page) => page,
+                Err(e) => return format!("Fetching the messages failed: {}", e)
+            };
+            let exhausted = (page.len() as u64) < limit;
+            before = page.last().map(|message| message.id);
+            let reached_since = page.iter().any(|message| filter.since.is_some_and(|since| message.id <= since));
+            scanned.extend(page.into_iter().filter(|message| filter.since.is_none_or(|since| message.id > since)));
+            if exhausted || reached_since || before.is_none() {
+                break;
+            }
+        }
+
+        let mut new_members = HashSet::new();
+        if filter.new_members {
+            let authors: HashSet<UserId> = scanned.iter().map(|message| message.author.id).collect();
+            for author in authors {
+                if self.joined_recently(ctx, author).await {
+                    new_members.insert(author);
+                }
+            }
+        }
+
+        let oldest_deletable = Utc::now() - chrono::Duration::days(BULK_DELETE_MAX_AGE_DAYS);
+        let (matching, too_old): (Vec<&Message>, Vec<&Message>) = scanned.iter()
+            .filter(|message| filter.matches(message, &new_members))
+            .partition(|message| message.id.created_at() > oldest_deletable);
+        let locations: Vec<MessageLocation> = matching.iter().map(|message| (message.id, channel)).collect();
+        let snapshots: Vec<MessageSnapshot> = matching.iter().map(|message| MessageSnapshot::from(*message)).collect();
+        // Taken out of the cache so their deletion isn't logged
+        for (message_id, _) in &locations {
+            self.message_cache.remove(*message_id);
+        }
+        let transcript = self.archive_messages(&snapshots, &format!("Purged by {} in {}", command.user.id, channel)).await;
+        let (deleted, failed) = delete_messages(&self.http, &locations).await;
+
+        if deleted > 0 {
+            self._log.attach(format!("purge-{}-{}.txt", channel, Utc::now().format("%Y%m%d-%H%M%S")), transcript);
+
+            let mut authors: BTreeMap<UserId, usize> = BTreeMap::new();
+            for message in &matching {
+                *authors.entry(message.author.id).or_default() += 1;
+            }
+            self.log(LogEntry::MessagesPurged { actor: command.user.id, channel, deleted, authors: authors.into_iter().collect() });
+        }
+
+        let mut response = format!("Deleted {} of {} matching messages among the last {} in <#{}>.", deleted, matching.len(), scanned.len(), channel);
+        if !too_old.is_empty() {
+            response.push_str(&format!(" {} matching messages are older than {} days and can't be bulk deleted.", too_old.len(), BULK_DELETE_MAX_AGE_DAYS));
+        }
+        if let Some(e) = failed {
+            response.push_str(&format!(" Some deletes failed: {}", e));
+            self.log_error("Purging messages failed", e);
+        }
+        response
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+use serenity::model::id::GuildId;
+
+use crate::guild_shell::GuildConfig;
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[n]` upgrades a persisted config from schema version n to n + 1. Every change to the
+/// persisted shape gets one, even when `#[serde(default)]` covers it, so the version tells the shapes apart.
+const MIGRATIONS: [Migration; 2] = [
+    v0_to_v1,
+    v1_to_v2,
+];
+
+pub const CURRENT_SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// `FIELDS_ADDED[n]` are the fields version n + 1 started writing. A config written by some version
+/// holds every field up to it, which is how `complete_config_from_value` tells cut off configs apart.
+const FIELDS_ADDED: [&[&str]; MIGRATIONS.len()] = [
+    &["schema_version", "admin_roles", "history", "preset"],
+    &["warning_limit", "archive_retention_days"],
+];
+
+/// Version 0 has no schema version and predates admin roles, the change history and presets.
+/// Nothing needs upgrading, their defaults are what version 0 did.
+fn v0_to_v1(_config: &mut Map<String, Value>) {}
+
+/// Version 2 adds the warning limit, the archive retention and the removal date. Guilds from before
+/// didn't silence on warnings, so they keep it off instead of getting the default of 3.
+fn v1_to_v2(config: &mut Map<String, Value>) {
+    config.entry("warning_limit").or_insert(json!(0));
+}
+
+fn schema_version(config: &Map<String, Value>) -> Result<u32, String> {
+    match config.get("schema_version") {
+        Some(version) => version.as_u64().map(|v| v as u32).ok_or_else(|| format!("Schema version {} is not a number", version)),
+        None => Ok(0)
+    }
+}
+
+/// Upgrades a raw config written by any earlier version of bussy to the current schema version.
+fn migrate(config: &mut Map<String, Value>) -> Result<(), String> {
+    let version = schema_version(config)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!("Config has schema version {}, but this version of bussy only knows up to {}", version, CURRENT_SCHEMA_VERSION));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(config);
+    }
+    config.insert("schema_version".into(), json!(CURRENT_SCHEMA_VERSION));
+    Ok(())
+}
+
+/// Upgrades a raw config written by any earlier version of bussy and deserializes it.
+pub fn config_from_value(mut value: Value) -> Result<GuildConfig, String> {
+    let config = value.as_object_mut().ok_or("Config is not a map")?;
+    migrate(config)?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Like `config_from_value`, but a field that the config's schema version always writes is an error
+/// when it is missing, instead of taking its default. For entries that may have been cut off.
+pub fn complete_config_from_value(mut value: Value) -> Result<GuildConfig, String> {
+    let config = value.as_object_mut().ok_or("Config is not a map")?;
+    let version = schema_version(config)?.min(CURRENT_SCHEMA_VERSION) as usize;
+    let added_later: Vec<&str> = FIELDS_ADDED[version..].iter().flat_map(|fields| fields.iter().copied()).collect();
+    let written = serde_json::to_value(GuildConfig::default()).map_err(|e| e.to_string())?;
+    let missing = written.as_object().into_iter().flat_map(|fields| fields.keys())
+        .find(|field| !added_later.contains(&field.as_str()) && !config.contains_key(*field));
+    if let Some(missing) = missing {
+        return Err(format!("{} is missing", missing));
+    }
+    config_from_value(value)
+}
+
+/// Parses a YAML map of guild ids to configs of any schema version. The key is the guild the config
+/// belongs to, whatever its `guild_id` says.
+pub fn configs_from_yaml(data: &str) -> Result<HashMap<GuildId, GuildConfig>, String> {
+    parse_configs(data, config_from_value)
+}
+
+/// Like `configs_from_yaml`, but entries missing a field are rejected, see `complete_config_from_value`.
+pub fn complete_configs_from_yaml(data: &str) -> Result<HashMap<GuildId, GuildConfig>, String> {
+    parse_configs(data, complete_config_from_value)
+}
+
+fn parse_configs(data: &str, from_value: fn(Value) -> Result<GuildConfig, String>) -> Result<HashMap<GuildId, GuildConfig>, String> {
+    let raw: HashMap<GuildId, Value> = serde_yaml::from_str(data).map_err(|e| e.to_string())?;
+    raw.into_iter()
+        .map(|(id, value)| {
+            let mut config = from_value(value).map_err(|e| format!("guild {}: {}", id, e))?;
+            config.guild_id = id;
+            Ok((id, config))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = include_str!("../fixtures/schema/v0.yml");
+    const V1: &str = include_str!("../fixtures/schema/v1.yml");
+    const CURRENT: &str = include_str!("../fixtures/schema/current.yml");
+    const PARTIAL: &str = include_str!("../fixtures/schema/partial.yml");
+    const GARBAGE: &str = include_str!("../fixtures/schema/garbage.yml");
+    const MISSING_GUILD_ID: &str = include_str!("../fixtures/schema/missing_guild_id.yml");
+
+    fn load(data: &str) -> GuildConfig {
+        let mut configs = configs_from_yaml(data).unwrap();
+        assert_eq!(configs.len(), 1);
+        configs.remove(&GuildId(910640456457666631)).unwrap()
+    }
+
+    fn schema_version(config: &GuildConfig) -> Value {
+        serde_json::to_value(config).unwrap()["schema_version"].clone()
+    }
+
+    #[test]
+    fn v0_loads_with_defaults() {
+        let config = load(V0);
+        assert_eq!(schema_version(&config), json!(CURRENT_SCHEMA_VERSION));
+        assert_eq!(*config.log_channel, Some(910640456457666632.into()));
+        assert_eq!(*config.message_pressure, 12.0);
+        assert_eq!(*config.warning_limit, 0);
+        assert_eq!(*config.archive_retention_days, 30);
+        assert!(config.history.dump(1).is_none());
+        assert!(config.preset.is_none());
+    }
+
+    #[test]
+    fn v1_keeps_warnings_off() {
+        let config = load(V1);
+        assert_eq!(schema_version(&config), json!(CURRENT_SCHEMA_VERSION));
+        assert_eq!(*config.warning_limit, 0);
+        assert_eq!(config.preset.as_deref(), Some("strict"));
+        assert!(config.history.dump(1).is_some());
+    }
+
+    #[test]
+    fn current_round_trips() {
+        let config = load(CURRENT);
+        assert_eq!(*config.warning_limit, 5);
+        assert_eq!(*config.archive_retention_days, 7);
+        assert!(config.removed_at.is_some());
+
+        let serialized = serde_yaml::to_string(&HashMap::from([(config.guild_id, &config)])).unwrap();
+        let reloaded = load(&serialized);
+        assert_eq!(serde_json::to_value(&reloaded).unwrap(), serde_json::to_value(&config).unwrap());
+    }
+
+    #[test]
+    fn partial_takes_defaults() {
+        let config = load(PARTIAL);
+        assert_eq!(*config.message_pressure, 20.0);
+        assert_eq!(*config.warning_limit, 0);
+        assert_eq!(*config.log_channel, None);
+    }
+
+    #[test]
+    fn guild_id_comes_from_the_key() {
+        let config = load(MISSING_GUILD_ID);
+        assert_eq!(config.guild_id, GuildId(910640456457666631));
+        assert_eq!(*config.log_channel, Some(910640456457666632.into()));
+
+        let moved = CURRENT.replacen("guild_id: 910640456457666631", "guild_id: 1", 1);
+        assert_eq!(load(&moved).guild_id, GuildId(910640456457666631));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(configs_from_yaml(GARBAGE).is_err());
+        assert!(config_from_value(json!("not a config")).is_err());
+        assert!(config_from_value(json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1 })).is_err());
+    }
+
+    #[test]
+    fn complete_configs_of_every_version_are_accepted() {
+        for data in [V0, V1, CURRENT] {
+            assert_eq!(complete_configs_from_yaml(data).unwrap().len(), 1);
+        }
+        assert!(complete_configs_from_yaml(PARTIAL).is_err());
+    }
+
+    #[test]
+    fn cut_off_configs_are_incomplete() {
+        let cut = &CURRENT[..CURRENT.find("message_pressure").unwrap()];
+        assert!(configs_from_yaml(cut).is_ok());
+        assert!(complete_configs_from_yaml(cut).unwrap_err().contains("is missing"));
+        assert!(complete_config_from_value(json!({ "schema_version": null, "guild_id": 1 })).is_err());
+    }
+
+    #[test]
+    fn new_fields_keep_their_value_when_migrated() {
+        let config = config_from_value(json!({ "schema_version": 1, "guild_id": 1, "warning_limit": 4 })).unwrap();
+        assert_eq!(*config.warning_limit, 4);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use serenity::client::Context;
+use serenity::http::AttachmentType;
+use serenity::model::channel::{GuildChannel, Message};
+use serenity::model::guild::Role;
+use serenity::model::id::{ChannelId, GuildId, InteractionId};
+use serenity::model::interactions::application_command::{ApplicationCommandInteraction, ResolvedTarget};
+use serenity::model::interactions::InteractionResponseType;
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use serenity::prelude::{SerenityError, TypeMapKey};
+use tokio::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::{GuildShells, request_config, ShellEvent};
+use crate::guild_shell::GuildConfig;
+use crate::schema::config_from_value;
+
+const CONFIRM_LOAD_ID: &str = "load_settings_confirm";
+const CANCEL_LOAD_ID: &str = "load_settings_cancel";
+/// Name of the message context menu command that loads settings from the message's attachment.
+pub const LOAD_FROM_MESSAGE_COMMAND: &str = "Load bussy settings";
+/// Settings files larger than this are refused without downloading them.
+const MAX_SETTINGS_FILE_SIZE: u64 = 256 * 1024;
+/// Previews can't be applied after this long. Discord stops accepting responses to the command by then anyway.
+const PENDING_SETTINGS_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Settings waiting for the confirm button of their preview.
+pub struct PendingLoad {
+    guild_id: GuildId,
+    config: GuildConfig,
+    // The config the preview was compared against, applying is refused if it changed since
+    previewed: GuildConfig,
+    created: Instant,
+}
+
+/// Settings uploaded with `/load_settings`, keyed by the id of the command that previewed them.
+pub struct PendingSettings;
+
+impl TypeMapKey for PendingSettings {
+    type Value = HashMap<InteractionId, PendingLoad>;
+}
+
+fn remove_expired(pending: &mut HashMap<InteractionId, PendingLoad>) {
+    pending.retain(|_, load| load.created.elapsed() < PENDING_SETTINGS_TIMEOUT);
+}
+
+/// Parses a guild config from either JSON or YAML, upgrading settings exported by older versions.
+pub fn parse_settings(raw: &str) -> Result<GuildConfig, String> {
+    let value: Value = match serde_json::from_str(raw) {
+        Ok(value) => value,
+        Err(json_error) => serde_yaml::from_str(raw)
+            .map_err(|yaml_error| format!("Not valid JSON ({}) or YAML ({})", json_error, yaml_error))?
+    };
+    config_from_value(value)
+}
+
+/// Sends the current config as a `.json` or `.yaml` file, depending on the `format` option.
+pub async fn dump_settings(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+    let guild_id = command.guild_id.expect("Must be used in a guild");
+    let format = command.data.options.first().and_then(|o| o.value.as_ref()).and_then(|v| v.as_str()).unwrap_or("json");
+
+    command.create_interaction_response(&ctx, |resp| {
+        resp.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+    }).await?;
+
+    let serialized = match request_config(ctx, &guild_id).await {
+        Some(config) if format == "yaml" => serde_yaml::to_string(&config).map_err(|e| e.to_string()),
+        Some(config) => serde_json::to_string_pretty(&config).map_err(|e| e.to_string()),
+        None => Err("The guild shell is not running".into())
+    };
+
+    match serialized {
+        Ok(text) => {
+            command.create_followup_message(&ctx, |msg| {
+                msg.content("Current settings. Load them again by using the \"Load bussy settings\" app on a message with this file attached.")
+                    .add_file(AttachmentType::Bytes { data: text.into_bytes().into(), filename: format!("bussy_settings_{}.{}", guild_id, format) })
+            }).await?;
+        }
+        Err(e) => {
+            command.create_followup_message(&ctx, |msg| {
+                msg.content(format!("Failed to convert config into {}! This should never happen. Error: {}", format, e))
+            }).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Finds the message the settings should be loaded from, either the target of the context menu
+/// command or the message linked in the `message` option.
+async fn find_settings_message(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<Message, String> {
+    if let Some(ResolvedTarget::Message(message)) = &command.data.target {
+        return Ok(message.clone());
+    }
+
+    let reference = command.data.options.first().and_then(|o| o.value.as_ref()).and_then(|v| v.as_str()).unwrap_or("");
+    // Message links look like https://discord.com/channels/<guild>/<channel>/<message>
+    let mut ids = reference.trim().rsplit('/');
+    let message_id = ids.next().and_then(|id| id.parse::<u64>().ok()).ok_or(format!("{} is not a message link or id", reference))?;
+    let channel_id = ids.next().and_then(|id| id.parse::<u64>().ok()).map(ChannelId::from).unwrap_or(command.channel_id);
+
+    ctx.http.get_message(channel_id.into(), message_id).await.map_err(|e| format!("Couldn't fetch the message: {}", e))
+}
+
+/// Downloads the settings file attached to the referenced message.
+async fn download_settings(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<String, String> {
+    let message = find_settings_message(ctx, command).await?;
+    let attachment = message.attachments.first().ok_or("The message has no settings file attached")?;
+    if attachment.size > MAX_SETTINGS_FILE_SIZE {
+        return Err(format!("{} is too large to be a settings file", attachment.filename));
+    }
+
+    let bytes = attachment.download().await.map_err(|e| format!("Couldn't download {}: {}", attachment.filename, e))?;
+    String::from_utf8(bytes).map_err(|_| format!("{} is not a text file", attachment.filename))
+}
+
+/// Validates the settings, stores them as pending under the preview's id and returns the changes they would make.
+async fn prepare_settings(ctx: &Context, preview: InteractionId, guild_id: GuildId, raw: &str) -> Result<String, String> {
+    let mut config = parse_settings(raw)?;
+    config.guild_id = guild_id;
+    config.load_names();
+
+    let guild = ctx.http.get_guild(guild_id.into()).await.map_err(|e| e.to_string())?;
+    let channels = guild_id.channels(&ctx).await.map_err(|e| e.to_string())?;
+    let roles: Vec<&Role> = guild.roles.values().collect();
+    let channels: Vec<&GuildChannel> = channels.values().collect();
+
+    let errors = config.validate(&roles, &channels);
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    let mut current = request_config(ctx, &guild_id).await.ok_or("The guild shell is not running")?;
+    let diff = current.diff(&mut config);
+    {
+        let mut data = ctx.data.write().await;
+        let pending = data.get_mut::<PendingSettings>().unwrap();
+        remove_expired(pending);
+        pending.insert(preview, PendingLoad { guild_id, config, previewed: current, created: Instant::now() });
+    }
+
+    if diff.is_empty() {
+        Ok("The loaded settings are identical to the current ones.".into())
+    } else {
+        Ok(diff.iter().map(|(field, old, new)| format!("**{}**: {} → {}", field, old, new)).collect::<Vec<String>>().join("\n"))
+    }
+}
+
+/// Responds to `/load_settings` or the message context menu command with the changes the settings
+/// would make and buttons to apply or discard them.
+pub async fn preview_settings(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+    let guild_id = command.guild_id.expect("Must be used in a guild");
+    // Downloading and validating can take longer than the interaction response window
+    command.create_interaction_response(&ctx, |resp| {
+        resp.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+    }).await?;
+
+    let prepared = match download_settings(ctx, command).await {
+        Ok(raw) => prepare_settings(ctx, command.id, guild_id, &raw).await,
+        Err(e) => Err(e)
+    };
+    let (title, description, valid) = match prepared {
+        Ok(diff) => ("Load these settings?", diff, true),
+        Err(e) => ("Settings can't be loaded", e, false)
+    };
+
+    command.create_followup_message(&ctx, |msg| {
+        msg.create_embed(|e| e.title(title).description(description));
+        if valid {
+            msg.components(|comp| {
+                comp.create_action_row(|row| {
+                    row
+                        .create_button(|b| b.label("Apply").style(ButtonStyle::Danger).custom_id(format!("{}:{}", CONFIRM_LOAD_ID, command.id)))
+                        .create_button(|b| b.label("Cancel").style(ButtonStyle::Secondary).custom_id(format!("{}:{}", CANCEL_LOAD_ID, command.id)))
+                })
+            });
+        }
+        msg
+    }).await?;
+    Ok(())
+}
+
+/// Handles the buttons of a settings preview. Returns false if the component is not one of them.
+pub async fn resolve_settings(ctx: &Context, component: &MessageComponentInteraction) -> bool {
+    let (action, preview) = match component.data.custom_id.split_once(':') {
+        Some((action, preview)) if action == CONFIRM_LOAD_ID || action == CANCEL_LOAD_ID => (action, preview),
+        _ => return false
+    };
+    let guild_id = match component.guild_id {
+        Some(id) => id,
+        None => return false
+    };
+
+    let pending = {
+        let mut data = ctx.data.write().await;
+        let pending = data.get_mut::<PendingSettings>().unwrap();
+        remove_expired(pending);
+        preview.parse::<u64>().ok().and_then(|id| pending.remove(&InteractionId(id)))
+    };
+    let text = match pending {
+        Some(load) if load.guild_id == guild_id => {
+            if action == CANCEL_LOAD_ID {
+                "Loading settings cancelled."
+            } else {
+                apply_pending(ctx, component, load).await
+            }
+        }
+        _ => "These settings are no longer pending."
+    };
+
+    if let Err(e) = component.create_interaction_response(&ctx, |resp| {
+        resp.kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|data| {
+                data.create_embed(|e| e.title("Load settings").description(text))
+                    .components(|comp| comp)
+            })
+    }).await {
+        warn!("Couldn't respond to settings confirmation: {}", e);
+    }
+    true
+}
+
+/// Sends the pending settings to the shell, unless its config changed since the preview.
+async fn apply_pending(ctx: &Context, component: &MessageComponentInteraction, mut load: PendingLoad) -> &'static str {
+    let mut current = match request_config(ctx, &load.guild_id).await {
+        Some(config) => config,
+        None => return "The guild shell is not running, settings were not applied."
+    };
+    if !current.diff(&mut load.previewed).is_empty() {
+        return "The settings were changed since this preview, load the file again to see what it would change now.";
+    }
+
+    let channel = ctx.data.read().await.get::<GuildShells>().unwrap().get(&load.guild_id).map(|s| s.channel.clone());
+    match channel {
+        Some(channel) if channel.send(ShellEvent::LoadConfig(component.user.id, load.config)).await.is_ok() => "Settings applied.",
+        _ => "The guild shell is not running, settings were not applied."
+    }
+}
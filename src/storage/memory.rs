@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serenity::model::id::GuildId;
+
+use crate::archive::MessageArchive;
+use crate::cases::CaseLog;
+use crate::guild_shell::{GuildConfig, RuntimeState};
+use crate::user_records::UserRecords;
+use crate::storage::ShellStore;
+
+/// Keeps configs only for the lifetime of the process, for tests and throwaway instances.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    configs: Mutex<HashMap<GuildId, GuildConfig>>,
+    states: Mutex<HashMap<GuildId, RuntimeState>>,
+    cases: Mutex<HashMap<GuildId, CaseLog>>,
+    user_records: Mutex<HashMap<GuildId, UserRecords>>,
+    archives: Mutex<HashMap<GuildId, MessageArchive>>,
+}
+
+impl ShellStore for MemoryStore {
+    fn load_all(&self) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+        (self.configs.lock().unwrap().clone(), Vec::new())
+    }
+
+    fn load(&self, guild_id: GuildId) -> Result<Option<GuildConfig>, String> {
+        Ok(self.configs.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save(&self, config: &GuildConfig) -> Result<(), String> {
+        self.configs.lock().unwrap().insert(config.guild_id, config.clone());
+        Ok(())
+    }
+
+    fn delete(&self, guild_id: GuildId) -> Result<(), String> {
+        self.configs.lock().unwrap().remove(&guild_id);
+        self.states.lock().unwrap().remove(&guild_id);
+        self.cases.lock().unwrap().remove(&guild_id);
+        self.user_records.lock().unwrap().remove(&guild_id);
+        self.archives.lock().unwrap().remove(&guild_id);
+        Ok(())
+    }
+
+    fn load_state(&self, guild_id: GuildId) -> Result<Option<RuntimeState>, String> {
+        Ok(self.states.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_state(&self, guild_id: GuildId, state: &RuntimeState) -> Result<(), String> {
+        self.states.lock().unwrap().insert(guild_id, state.clone());
+        Ok(())
+    }
+
+    fn load_cases(&self, guild_id: GuildId) -> Result<Option<CaseLog>, String> {
+        Ok(self.cases.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_cases(&self, guild_id: GuildId, cases: &CaseLog) -> Result<(), String> {
+        self.cases.lock().unwrap().insert(guild_id, cases.clone());
+        Ok(())
+    }
+
+    fn load_user_records(&self, guild_id: GuildId) -> Result<Option<UserRecords>, String> {
+        Ok(self.user_records.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_user_records(&self, guild_id: GuildId, records: &UserRecords) -> Result<(), String> {
+        self.user_records.lock().unwrap().insert(guild_id, records.clone());
+        Ok(())
+    }
+
+    fn load_archive(&self, guild_id: GuildId) -> Result<Option<MessageArchive>, String> {
+        Ok(self.archives.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_archive(&self, guild_id: GuildId, archive: &MessageArchive) -> Result<(), String> {
+        self.archives.lock().unwrap().insert(guild_id, archive.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+#[cfg(test)]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serenity::model::id::GuildId;
+use serenity::prelude::TypeMapKey;
+
+use crate::archive::MessageArchive;
+use crate::cases::CaseLog;
+use crate::guild_shell::{GuildConfig, RuntimeState};
+use crate::user_records::UserRecords;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+pub use yaml::YamlStore;
+
+mod memory;
+mod sqlite;
+mod yaml;
+
+/// Where guild configs are persisted between restarts.
+pub trait ShellStore: Debug + Send + Sync {
+    /// Returns every stored config, along with problems that came up reading them and should be reported.
+    fn load_all(&self) -> (HashMap<GuildId, GuildConfig>, Vec<String>);
+    fn load(&self, guild_id: GuildId) -> Result<Option<GuildConfig>, String>;
+    fn save(&self, config: &GuildConfig) -> Result<(), String>;
+    /// Saves several configs at once. Backends that rewrite everything on each save should override this.
+    fn save_all(&self, configs: &[GuildConfig]) -> Result<(), String> {
+        for config in configs {
+            self.save(config)?;
+        }
+        Ok(())
+    }
+    /// Removes everything stored for the guild.
+    fn delete(&self, guild_id: GuildId) -> Result<(), String>;
+
+    fn load_state(&self, guild_id: GuildId) -> Result<Option<RuntimeState>, String>;
+    fn save_state(&self, guild_id: GuildId, state: &RuntimeState) -> Result<(), String>;
+
+    fn load_cases(&self, guild_id: GuildId) -> Result<Option<CaseLog>, String>;
+    fn save_cases(&self, guild_id: GuildId, cases: &CaseLog) -> Result<(), String>;
+
+    fn load_user_records(&self, guild_id: GuildId) -> Result<Option<UserRecords>, String>;
+    fn save_user_records(&self, guild_id: GuildId, records: &UserRecords) -> Result<(), String>;
+
+    fn load_archive(&self, guild_id: GuildId) -> Result<Option<MessageArchive>, String>;
+    fn save_archive(&self, guild_id: GuildId, archive: &MessageArchive) -> Result<(), String>;
+}
+
+/// Runs a store operation on the blocking thread pool, file and database writes would stall the
+/// runtime otherwise.
+pub async fn blocking<T, F>(store: &Arc<dyn ShellStore>, operation: F) -> Result<T, String>
+    where T: Send + 'static, F: FnOnce(&dyn ShellStore) -> Result<T, String> + Send + 'static {
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || operation(store.as_ref())).await
+        .map_err(|e| format!("The storage task failed: {}", e))?
+}
+
+pub struct ShellStorage;
+
+impl TypeMapKey for ShellStorage {
+    type Value = Arc<dyn ShellStore>;
+}
+
+/// Picks the backend from the location: `:memory:` keeps configs in memory only, `.db`, `.sqlite`
+/// and `.sqlite3` files are SQLite databases and anything else is a YAML file.
+pub fn open_store(location: &str) -> Result<Arc<dyn ShellStore>, String> {
+    if location == ":memory:" {
+        return Ok(Arc::new(MemoryStore::default()));
+    }
+
+    let path = Path::new(location);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("db") | Some("sqlite") | Some("sqlite3") => Ok(Arc::new(SqliteStore::open(path)?)),
+        _ => Ok(Arc::new(YamlStore::open(path)))
+    }
+}
+
+/// Loads every stored config like `open_store` followed by `load_all`, but leaves the storage
+/// untouched, even when it is corrupt.
+pub fn check_store(location: &str) -> Result<(HashMap<GuildId, GuildConfig>, Vec<String>), String> {
+    if location == ":memory:" {
+        return Ok((HashMap::new(), Vec::new()));
+    }
+
+    let path = Path::new(location);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("db") | Some("sqlite") | Some("sqlite3") => Ok(SqliteStore::open_read_only(path)?.load_all()),
+        _ => Ok(yaml::check(path))
+    }
+}
+
+/// An empty directory for a test's files, cleared at the start of every run.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bussy-test-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(guild_id: u64, warning_limit: &str) -> GuildConfig {
+        let mut config = GuildConfig::new(GuildId(guild_id));
+        config.get_configurable_fields().into_iter().find(|f| f.get_name() == "warning_limit").unwrap()
+            .set_value(warning_limit.into()).unwrap();
+        config
+    }
+
+    fn warning_limit(config: Option<GuildConfig>) -> serde_json::Value {
+        serde_json::to_value(config.expect("config is stored")).unwrap()["warning_limit"].clone()
+    }
+
+    /// Saves, updates, loads and deletes two guilds through the trait.
+    fn round_trip(store: &dyn ShellStore) {
+        store.save(&config(1, "1")).unwrap();
+        store.save_all(&[config(1, "2"), config(2, "3")]).unwrap();
+        store.save_state(GuildId(1), &RuntimeState::default()).unwrap();
+        store.save_cases(GuildId(1), &CaseLog::default()).unwrap();
+
+        let (configs, problems) = store.load_all();
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(configs.len(), 2);
+        assert_eq!(warning_limit(store.load(GuildId(1)).unwrap()), 2);
+        assert_eq!(warning_limit(store.load(GuildId(2)).unwrap()), 3);
+        assert!(store.load_state(GuildId(1)).unwrap().is_some());
+        assert!(store.load(GuildId(3)).unwrap().is_none());
+
+        store.delete(GuildId(1)).unwrap();
+        assert!(store.load(GuildId(1)).unwrap().is_none());
+        assert!(store.load_state(GuildId(1)).unwrap().is_none());
+        assert!(store.load_cases(GuildId(1)).unwrap().is_none());
+        assert_eq!(store.load_all().0.len(), 1);
+    }
+
+    #[test]
+    fn memory_store_round_trips() {
+        round_trip(open_store(":memory:").unwrap().as_ref());
+    }
+
+    #[test]
+    fn sqlite_store_round_trips() {
+        let path = test_dir("sqlite-round-trip").join("shells.db");
+        round_trip(open_store(path.to_str().unwrap()).unwrap().as_ref());
+
+        // Only the deleted guild is gone after reopening
+        let reopened = SqliteStore::open(&path).unwrap();
+        assert_eq!(warning_limit(reopened.load(GuildId(2)).unwrap()), 3);
+        assert!(reopened.load(GuildId(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn sqlite_check_reads_without_writing() {
+        let path = test_dir("sqlite-check").join("shells.db");
+        SqliteStore::open(&path).unwrap().save(&config(1, "4")).unwrap();
+
+        let read_only = SqliteStore::open_read_only(&path).unwrap();
+        assert_eq!(warning_limit(read_only.load(GuildId(1)).unwrap()), 4);
+        assert!(read_only.save(&config(1, "5")).is_err());
+        assert!(check_store(test_dir("sqlite-check-missing").join("shells.db").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn yaml_store_round_trips() {
+        let path = test_dir("yaml-round-trip").join("shells.yml");
+        round_trip(open_store(path.to_str().unwrap()).unwrap().as_ref());
+
+        let reopened = YamlStore::open(&path);
+        assert_eq!(warning_limit(reopened.load(GuildId(2)).unwrap()), 3);
+        assert!(reopened.load(GuildId(1)).unwrap().is_none());
+        assert!(reopened.load_all().1.is_empty());
+    }
+}
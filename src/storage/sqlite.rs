@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use serenity::model::id::GuildId;
+
+use crate::archive::MessageArchive;
+use crate::cases::CaseLog;
+use crate::guild_shell::{GuildConfig, RuntimeState};
+use crate::user_records::UserRecords;
+use crate::schema::config_from_value;
+use crate::storage::ShellStore;
+
+/// Stores one row per guild, so saving a guild only touches that guild's config.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS guild_configs (guild_id INTEGER PRIMARY KEY, config TEXT NOT NULL)",
+            [],
+        ).map_err(|e| format!("Couldn't create the config table: {}", e))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS guild_states (guild_id INTEGER PRIMARY KEY, state TEXT NOT NULL)",
+            [],
+        ).map_err(|e| format!("Couldn't create the state table: {}", e))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS guild_cases (guild_id INTEGER PRIMARY KEY, cases TEXT NOT NULL)",
+            [],
+        ).map_err(|e| format!("Couldn't create the case table: {}", e))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS guild_user_records (guild_id INTEGER PRIMARY KEY, records TEXT NOT NULL)",
+            [],
+        ).map_err(|e| format!("Couldn't create the user record table: {}", e))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS guild_message_archives (guild_id INTEGER PRIMARY KEY, archive TEXT NOT NULL)",
+            [],
+        ).map_err(|e| format!("Couldn't create the message archive table: {}", e))?;
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+
+    /// Opens an existing database without creating or changing anything, any write fails.
+    pub fn open_read_only(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+}
+
+fn upsert(connection: &Connection, config: &GuildConfig) -> Result<(), String> {
+    let serialized = serde_json::to_string(config).map_err(|e| format!("Can't serialize config for {}: {}", config.guild_id, e))?;
+    connection.execute(
+        "INSERT INTO guild_configs (guild_id, config) VALUES (?1, ?2) ON CONFLICT(guild_id) DO UPDATE SET config = excluded.config",
+        params![config.guild_id.0 as i64, serialized],
+    ).map_err(|e| format!("Couldn't save config for {}: {}", config.guild_id, e))?;
+    Ok(())
+}
+
+impl ShellStore for SqliteStore {
+    fn load_all(&self) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+        let connection = self.connection.lock().unwrap();
+        let mut configs = HashMap::new();
+        let mut problems = Vec::new();
+
+        let rows = connection.prepare("SELECT guild_id, config FROM guild_configs")
+            .and_then(|mut statement| {
+                statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()
+            });
+        match rows {
+            Ok(rows) => {
+                for (guild_id, config) in rows {
+                    match serde_json::from_str(&config).map_err(|e| e.to_string()).and_then(config_from_value) {
+                        Ok(mut config) => {
+                            // The row is the guild the config belongs to, like the keys of the YAML file
+                            config.guild_id = GuildId(guild_id as u64);
+                            configs.insert(config.guild_id, config);
+                        }
+                        Err(e) => problems.push(format!("Config for guild {} could not be deserialized: {}", guild_id, e))
+                    }
+                }
+            }
+            Err(e) => problems.push(format!("Couldn't read configs: {}", e))
+        }
+        (configs, problems)
+    }
+
+    fn load(&self, guild_id: GuildId) -> Result<Option<GuildConfig>, String> {
+        let connection = self.connection.lock().unwrap();
+        let config: Option<String> = connection.query_row(
+            "SELECT config FROM guild_configs WHERE guild_id = ?1",
+            params![guild_id.0 as i64],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("Couldn't load config for {}: {}", guild_id, e))?;
+
+        match config {
+            Some(config) => serde_json::from_str(&config).map_err(|e| e.to_string()).and_then(config_from_value)
+                .map(|mut config| {
+                    config.guild_id = guild_id;
+                    Some(config)
+                })
+                .map_err(|e| format!("Config for guild {} could not be deserialized: {}", guild_id, e)),
+            None => Ok(None)
+        }
+    }
+
+    fn save(&self, config: &GuildConfig) -> Result<(), String> {
+        upsert(&self.connection.lock().unwrap(), config)
+    }
+
+    fn save_all(&self, configs: &[GuildConfig]) -> Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        for config in configs {
+            upsert(&transaction, config)?;
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, guild_id: GuildId) -> Result<(), String> {
+        let connection = self.connection.lock().unwrap();
+        for table in ["guild_configs", "guild_states", "guild_cases", "guild_user_records", "guild_message_archives"] {
+            connection.execute(&format!("DELETE FROM {} WHERE guild_id = ?1", table), params![guild_id.0 as i64])
+                .map_err(|e| format!("Couldn't delete {} for {}: {}", table, guild_id, e))?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&self, guild_id: GuildId) -> Result<Option<RuntimeState>, String> {
+        let connection = self.connection.lock().unwrap();
+        let state: Option<String> = connection.query_row(
+            "SELECT state FROM guild_states WHERE guild_id = ?1",
+            params![guild_id.0 as i64],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("Couldn't load state for {}: {}", guild_id, e))?;
+
+        match state {
+            Some(state) => serde_json::from_str(&state).map(Some).map_err(|e| format!("State for guild {} could not be deserialized: {}", guild_id, e)),
+            None => Ok(None)
+        }
+    }
+
+    fn save_state(&self, guild_id: GuildId, state: &RuntimeState) -> Result<(), String> {
+        let serialized = serde_json::to_string(state).map_err(|e| format!("Can't serialize state for {}: {}", guild_id, e))?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_states (guild_id, state) VALUES (?1, ?2) ON CONFLICT(guild_id) DO UPDATE SET state = excluded.state",
+            params![guild_id.0 as i64, serialized],
+        ).map(|_| ()).map_err(|e| format!("Couldn't save state for {}: {}", guild_id, e))
+    }
+
+    fn load_cases(&self, guild_id: GuildId) -> Result<Option<CaseLog>, String> {
+        let connection = self.connection.lock().unwrap();
+        let cases: Option<String> = connection.query_row(
+            "SELECT cases FROM guild_cases WHERE guild_id = ?1",
+            params![guild_id.0 as i64],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("Couldn't load cases for {}: {}", guild_id, e))?;
+
+        match cases {
+            Some(cases) => serde_json::from_str(&cases).map(Some).map_err(|e| format!("Cases for guild {} could not be deserialized: {}", guild_id, e)),
+            None => Ok(None)
+        }
+    }
+
+    fn save_cases(&self, guild_id: GuildId, cases: &CaseLog) -> Result<(), String> {
+        let serialized = serde_json::to_string(cases).map_err(|e| format!("Can't serialize cases for {}: {}", guild_id, e))?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_cases (guild_id, cases) VALUES (?1, ?2) ON CONFLICT(guild_id) DO UPDATE SET cases = excluded.cases",
+            params![guild_id.0 as i64, serialized],
+        ).map(|_| ()).map_err(|e| format!("Couldn't save cases for {}: {}", guild_id, e))
+    }
+
+    fn load_user_records(&self, guild_id: GuildId) -> Result<Option<UserRecords>, String> {
+        let connection = self.connection.lock().unwrap();
+        let records: Option<String> = connection.query_row(
+            "SELECT records FROM guild_user_records WHERE guild_id = ?1",
+            params![guild_id.0 as i64],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("Couldn't load user records for {}: {}", guild_id, e))?;
+
+        match records {
+            Some(records) => serde_json::from_str(&records).map(Some).map_err(|e| format!("User records for guild {} could not be deserialized: {}", guild_id, e)),
+            None => Ok(None)
+        }
+    }
+
+    fn save_user_records(&self, guild_id: GuildId, records: &UserRecords) -> Result<(), String> {
+        let serialized = serde_json::to_string(records).map_err(|e| format!("Can't serialize user records for {}: {}", guild_id, e))?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_user_records (guild_id, records) VALUES (?1, ?2) ON CONFLICT(guild_id) DO UPDATE SET records = excluded.records",
+            params![guild_id.0 as i64, serialized],
+        ).map(|_| ()).map_err(|e| format!("Couldn't save user records for {}: {}", guild_id, e))
+    }
+
+    fn load_archive(&self, guild_id: GuildId) -> Result<Option<MessageArchive>, String> {
+        let connection = self.connection.lock().unwrap();
+        let archive: Option<String> = connection.query_row(
+            "SELECT archive FROM guild_message_archives WHERE guild_id = ?1",
+            params![guild_id.0 as i64],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("Couldn't load the message archive for {}: {}", guild_id, e))?;
+
+        match archive {
+            Some(archive) => serde_json::from_str(&archive).map(Some).map_err(|e| format!("Message archive for guild {} could not be deserialized: {}", guild_id, e)),
+            None => Ok(None)
+        }
+    }
+
+    fn save_archive(&self, guild_id: GuildId, archive: &MessageArchive) -> Result<(), String> {
+        let serialized = serde_json::to_string(archive).map_err(|e| format!("Can't serialize the message archive for {}: {}", guild_id, e))?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_message_archives (guild_id, archive) VALUES (?1, ?2) ON CONFLICT(guild_id) DO UPDATE SET archive = excluded.archive",
+            params![guild_id.0 as i64, serialized],
+        ).map(|_| ()).map_err(|e| format!("Couldn't save the message archive for {}: {}", guild_id, e))
+    }
+}
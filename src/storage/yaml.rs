@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serenity::model::id::GuildId;
+use tracing::{info, warn};
+
+use crate::archive::MessageArchive;
+use crate::cases::CaseLog;
+use crate::guild_shell::{GuildConfig, RuntimeState};
+use crate::user_records::UserRecords;
+use crate::schema::{complete_configs_from_yaml, configs_from_yaml};
+use crate::storage::ShellStore;
+
+/// How many previous versions of the shells file and each side file are kept next to them.
+const BACKUP_COUNT: usize = 5;
+/// Side files are saved every few seconds, so their backups are only rotated this often. Otherwise
+/// all of them would soon hold the same recent state.
+const SIDE_FILE_BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// `shells.yml` -> `shells.yml.<n>`, where 1 is the newest backup.
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Moves every backup one slot back, dropping the oldest, and copies the current file into the first
+/// slot. Nothing happens if the newest backup is younger than `interval`.
+fn rotate_backups(path: &Path, interval: Duration) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let newest_age = std::fs::metadata(backup_path(path, 1)).and_then(|m| m.modified()).ok()
+        .and_then(|modified| modified.elapsed().ok());
+    if newest_age.is_some_and(|age| age < interval) {
+        return Ok(());
+    }
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            std::fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    std::fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Writes `contents` to a temporary file, syncs it and renames it over `path`, so a crash
+/// leaves either the old or the new file but never a truncated one. Blocks, so callers on the
+/// runtime go through `storage::blocking`.
+fn write_atomically(path: &Path, contents: &[u8], backup_interval: Duration) -> std::io::Result<()> {
+    let temp = temp_path(path);
+    {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    rotate_backups(path, backup_interval)?;
+    std::fs::rename(&temp, path)?;
+
+    // Make the rename itself durable
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn read_shell_configs(path: &Path) -> Result<HashMap<GuildId, GuildConfig>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{} could not be read: {}", path.display(), e))?;
+    configs_from_yaml(&data).map_err(|e| format!("{} could not be deserialized: {}", path.display(), e))
+}
+
+/// Parses every top level guild entry on its own, so one broken or truncated entry doesn't take
+/// the other guilds down with it. Entries missing a field are rejected, a cut off entry would
+/// otherwise load with defaults in place of its lost values.
+fn salvage_configs(data: &str) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+    let mut entries: Vec<String> = Vec::new();
+    for line in data.lines() {
+        if line == "---" {
+            continue;
+        }
+        // Guild ids are the only unindented keys
+        if entries.is_empty() || (!line.is_empty() && !line.starts_with(' ')) {
+            entries.push(String::new());
+        }
+        let entry = entries.last_mut().unwrap();
+        entry.push_str(line);
+        entry.push('\n');
+    }
+
+    let mut configs = HashMap::new();
+    let mut problems = Vec::new();
+    for entry in entries {
+        match complete_configs_from_yaml(&entry) {
+            Ok(config) => configs.extend(config),
+            Err(e) => problems.push(format!("Entry starting with `{}` could not be salvaged: {}", entry.lines().next().unwrap_or(""), e))
+        }
+    }
+    (configs, problems)
+}
+
+/// Returns the contents of the newest backup of `path` that `read` accepts.
+fn newest_backup<T>(path: &Path, read: impl Fn(&Path) -> Result<T, String>) -> Option<(PathBuf, T)> {
+    for n in 1..=BACKUP_COUNT {
+        let backup = backup_path(path, n);
+        if !backup.exists() {
+            continue;
+        }
+        match read(&backup) {
+            Ok(configs) => return Some((backup, configs)),
+            Err(e) => warn!("Backup is not usable either: {}", e)
+        }
+    }
+    None
+}
+
+/// Reads the shells file without changing anything on disk. A missing file means no guilds were
+/// saved yet. A corrupt file is rebuilt from the entries that are still complete, with the newest usable
+/// backup filling in the guilds that don't. Returns whether the file was corrupt along with
+/// everything that went wrong.
+fn read_or_salvage(path: &Path) -> (HashMap<GuildId, GuildConfig>, Vec<String>, bool) {
+    if !path.exists() {
+        info!("{} does not exist, starting without saved shells", path.display());
+        return (HashMap::new(), Vec::new(), false);
+    }
+
+    let mut problems = match read_shell_configs(path) {
+        Ok(configs) => return (configs, Vec::new(), false),
+        Err(e) => vec![e]
+    };
+
+    let mut configs = match newest_backup(path, read_shell_configs) {
+        Some((backup, configs)) => {
+            problems.push(format!("Guilds that could not be salvaged were restored from backup {}, changes made after it was written are lost", backup.display()));
+            configs
+        }
+        None => {
+            problems.push("No usable backup was found".into());
+            HashMap::new()
+        }
+    };
+
+    if let Ok(data) = std::fs::read_to_string(path) {
+        let (salvaged, salvage_problems) = salvage_configs(&data);
+        problems.push(format!("Salvaged {} guild configs from the corrupt file", salvaged.len()));
+        problems.extend(salvage_problems);
+        configs.extend(salvaged);
+    }
+    (configs, problems, true)
+}
+
+/// Like `read_or_salvage`, but a corrupt file is also moved aside so the rebuilt configs replace it.
+fn load_shell_configs(path: &Path) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+    let (configs, mut problems, corrupt) = read_or_salvage(path);
+    if !corrupt {
+        return (configs, problems);
+    }
+
+    let mut quarantine = path.as_os_str().to_owned();
+    quarantine.push(format!(".corrupt-{}", Utc::now().format("%Y%m%d%H%M%S")));
+    match std::fs::rename(path, &quarantine) {
+        Ok(()) => problems.push(format!("The corrupt file was moved to {}", Path::new(&quarantine).display())),
+        Err(e) => problems.push(format!("The corrupt file could not be moved away: {}", e))
+    }
+
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    (configs, problems)
+}
+
+/// `shells.yml` -> `shells.<kind>.yml`
+fn side_path(path: &Path, kind: &str) -> PathBuf {
+    path.with_extension(format!("{}.yml", kind))
+}
+
+fn read_side_file<T: DeserializeOwned>(path: &Path) -> Result<HashMap<GuildId, T>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{} could not be read: {}", path.display(), e))?;
+    serde_yaml::from_str(&data).map_err(|e| format!("{} could not be deserialized: {}", path.display(), e))
+}
+
+/// Reads a file that keeps per guild data next to the shells file, like the runtime states. A
+/// broken file is replaced by its newest usable backup instead of salvaged.
+fn load_side_file<T: DeserializeOwned>(path: &Path) -> (HashMap<GuildId, T>, Option<String>) {
+    if !path.exists() {
+        return (HashMap::new(), None);
+    }
+    let e = match read_side_file(path) {
+        Ok(values) => return (values, None),
+        Err(e) => e
+    };
+    match newest_backup(path, read_side_file) {
+        Some((backup, values)) => (values, Some(format!("{}, restored it from backup {}", e, backup.display()))),
+        None => (HashMap::new(), Some(format!("{} and no usable backup was found, its contents are lost", e)))
+    }
+}
+
+fn write_side_file<T: Serialize>(path: &Path, values: &HashMap<GuildId, T>) -> Result<(), String> {
+    let serialized = serde_yaml::to_string(values).map_err(|e| format!("Can't serialize {}: {}", path.display(), e))?;
+    write_atomically(path, serialized.as_bytes(), SIDE_FILE_BACKUP_INTERVAL).map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+}
+
+/// Keeps every guild in a single YAML file, so each save rewrites all of them. Runtime states,
+/// cases, user records and archived messages live in their own files next to it.
+#[derive(Debug)]
+pub struct YamlStore {
+    path: PathBuf,
+    configs: Mutex<HashMap<GuildId, GuildConfig>>,
+    states: Mutex<HashMap<GuildId, RuntimeState>>,
+    cases: Mutex<HashMap<GuildId, CaseLog>>,
+    user_records: Mutex<HashMap<GuildId, UserRecords>>,
+    archives: Mutex<HashMap<GuildId, MessageArchive>>,
+    load_problems: Mutex<Vec<String>>,
+}
+
+impl YamlStore {
+    /// Reads the files right away, so saving a single guild never drops the others.
+    pub fn open(path: &Path) -> Self {
+        let (configs, mut problems) = load_shell_configs(path);
+        let (states, state_problem) = load_side_file(&side_path(path, "state"));
+        let (cases, case_problem) = load_side_file(&side_path(path, "cases"));
+        let (user_records, user_record_problem) = load_side_file(&side_path(path, "users"));
+        let (archives, archive_problem) = load_side_file(&side_path(path, "archive"));
+        problems.extend(state_problem);
+        problems.extend(case_problem);
+        problems.extend(user_record_problem);
+        problems.extend(archive_problem);
+        YamlStore {
+            path: path.to_path_buf(),
+            configs: Mutex::new(configs),
+            states: Mutex::new(states),
+            cases: Mutex::new(cases),
+            user_records: Mutex::new(user_records),
+            archives: Mutex::new(archives),
+            load_problems: Mutex::new(problems),
+        }
+    }
+
+    fn write(&self, configs: &HashMap<GuildId, GuildConfig>) -> Result<(), String> {
+        let serialized = serde_yaml::to_string(configs).map_err(|e| format!("Can't serialize shells: {}", e))?;
+        write_atomically(&self.path, serialized.as_bytes(), Duration::ZERO).map_err(|e| format!("Couldn't save shells to {}: {}", self.path.display(), e))
+    }
+}
+
+/// Reads the shells file and the files next to it the way `YamlStore::open` does, but never
+/// renames, rotates or writes any of them.
+pub fn check(path: &Path) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+    let (configs, mut problems, _) = read_or_salvage(path);
+    for kind in ["state", "cases", "users", "archive"] {
+        let (_, problem) = load_side_file::<serde_yaml::Value>(&side_path(path, kind));
+        problems.extend(problem);
+    }
+    (configs, problems)
+}
+
+impl ShellStore for YamlStore {
+    fn load_all(&self) -> (HashMap<GuildId, GuildConfig>, Vec<String>) {
+        (self.configs.lock().unwrap().clone(), std::mem::take(&mut *self.load_problems.lock().unwrap()))
+    }
+
+    fn load(&self, guild_id: GuildId) -> Result<Option<GuildConfig>, String> {
+        Ok(self.configs.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save(&self, config: &GuildConfig) -> Result<(), String> {
+        let mut configs = self.configs.lock().unwrap();
+        configs.insert(config.guild_id, config.clone());
+        self.write(&configs)
+    }
+
+    fn save_all(&self, new_configs: &[GuildConfig]) -> Result<(), String> {
+        let mut configs = self.configs.lock().unwrap();
+        for config in new_configs {
+            configs.insert(config.guild_id, config.clone());
+        }
+        self.write(&configs)
+    }
+
+    fn delete(&self, guild_id: GuildId) -> Result<(), String> {
+        let mut configs = self.configs.lock().unwrap();
+        configs.remove(&guild_id);
+        self.write(&configs)?;
+
+        let mut states = self.states.lock().unwrap();
+        if states.remove(&guild_id).is_some() {
+            write_side_file(&side_path(&self.path, "state"), &states)?;
+        }
+        let mut cases = self.cases.lock().unwrap();
+        if cases.remove(&guild_id).is_some() {
+            write_side_file(&side_path(&self.path, "cases"), &cases)?;
+        }
+        let mut user_records = self.user_records.lock().unwrap();
+        if user_records.remove(&guild_id).is_some() {
+            write_side_file(&side_path(&self.path, "users"), &user_records)?;
+        }
+        let mut archives = self.archives.lock().unwrap();
+        if archives.remove(&guild_id).is_some() {
+            write_side_file(&side_path(&self.path, "archive"), &archives)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&self, guild_id: GuildId) -> Result<Option<RuntimeState>, String> {
+        Ok(self.states.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_state(&self, guild_id: GuildId, state: &RuntimeState) -> Result<(), String> {
+        let mut states = self.states.lock().unwrap();
+        states.insert(guild_id, state.clone());
+        write_side_file(&side_path(&self.path, "state"), &states)
+    }
+
+    fn load_cases(&self, guild_id: GuildId) -> Result<Option<CaseLog>, String> {
+        Ok(self.cases.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_cases(&self, guild_id: GuildId, cases: &CaseLog) -> Result<(), String> {
+        let mut all_cases = self.cases.lock().unwrap();
+        all_cases.insert(guild_id, cases.clone());
+        write_side_file(&side_path(&self.path, "cases"), &all_cases)
+    }
+
+    fn load_user_records(&self, guild_id: GuildId) -> Result<Option<UserRecords>, String> {
+        Ok(self.user_records.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_user_records(&self, guild_id: GuildId, records: &UserRecords) -> Result<(), String> {
+        let mut all_records = self.user_records.lock().unwrap();
+        all_records.insert(guild_id, records.clone());
+        write_side_file(&side_path(&self.path, "users"), &all_records)
+    }
+
+    fn load_archive(&self, guild_id: GuildId) -> Result<Option<MessageArchive>, String> {
+        Ok(self.archives.lock().unwrap().get(&guild_id).cloned())
+    }
+
+    fn save_archive(&self, guild_id: GuildId, archive: &MessageArchive) -> Result<(), String> {
+        let mut archives = self.archives.lock().unwrap();
+        archives.insert(guild_id, archive.clone());
+        write_side_file(&side_path(&self.path, "archive"), &archives)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::storage::test_dir;
+
+    /// Two complete guild entries, written the way `YamlStore` writes them.
+    fn shells_file(max_pressure: &str) -> String {
+        let mut configs = BTreeMap::new();
+        for id in [1, 2] {
+            let mut config = GuildConfig::new(GuildId(id));
+            config.get_configurable_fields().into_iter().find(|f| f.get_name() == "max_pressure").unwrap()
+                .set_value(max_pressure.into()).unwrap();
+            configs.insert(GuildId(id), config);
+        }
+        serde_yaml::to_string(&configs).unwrap()
+    }
+
+    fn max_pressure(config: &GuildConfig) -> serde_json::Value {
+        serde_json::to_value(config).unwrap()["max_pressure"].clone()
+    }
+
+    /// Cuts the second guild off in the middle of its max pressure and breaks the rest of the file.
+    fn corrupted(file: &str) -> String {
+        let second = file.find("\n2:").unwrap();
+        let cut = second + file[second..].find("max_pressure: ").unwrap() + "max_pressure: ".len() + 1;
+        format!("{}\n3: [\u{0}\u{0}\n", &file[..cut])
+    }
+
+    #[test]
+    fn writes_rotate_backups() {
+        let path = test_dir("yaml-rotate").join("shells.yml");
+        for n in 0..BACKUP_COUNT + 2 {
+            write_atomically(&path, n.to_string().as_bytes(), Duration::ZERO).unwrap();
+        }
+
+        let last = BACKUP_COUNT + 1;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), last.to_string());
+        for n in 1..=BACKUP_COUNT {
+            assert_eq!(std::fs::read_to_string(backup_path(&path, n)).unwrap(), (last - n).to_string());
+        }
+        assert!(!backup_path(&path, BACKUP_COUNT + 1).exists());
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn backups_wait_for_the_interval() {
+        let path = test_dir("yaml-rotate-interval").join("state.yml");
+        for n in 0..3 {
+            write_atomically(&path, n.to_string().as_bytes(), SIDE_FILE_BACKUP_INTERVAL).unwrap();
+        }
+
+        // The first write had nothing to back up, the second made a fresh backup and the third kept it
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "2");
+        assert_eq!(std::fs::read_to_string(backup_path(&path, 1)).unwrap(), "0");
+        assert!(!backup_path(&path, 2).exists());
+    }
+
+    #[test]
+    fn broken_side_file_is_restored_from_backup() {
+        let path = test_dir("yaml-side-file").join("shells.state.yml");
+        write_side_file(&path, &HashMap::from([(GuildId(1), RuntimeState::default())])).unwrap();
+        write_side_file(&path, &HashMap::from([(GuildId(2), RuntimeState::default())])).unwrap();
+        std::fs::write(&path, "1: [\u{0}").unwrap();
+
+        let (states, problem) = load_side_file::<RuntimeState>(&path);
+        assert!(states.contains_key(&GuildId(1)));
+        assert!(problem.unwrap().contains("restored it from backup"));
+    }
+
+    #[test]
+    fn salvage_keeps_complete_entries() {
+        let (configs, problems) = salvage_configs(&shells_file("60.0"));
+        assert_eq!(configs.len(), 2);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn salvage_rejects_cut_off_entries() {
+        let (configs, problems) = salvage_configs(&corrupted(&shells_file("60.0")));
+        assert_eq!(configs.keys().collect::<Vec<_>>(), vec![&GuildId(1)]);
+        // The cut off guild and the broken rest of the file
+        assert_eq!(problems.len(), 2);
+        assert!(problems.iter().any(|p| p.contains("guild 2") && p.contains("is missing")), "{:?}", problems);
+    }
+
+    #[test]
+    fn corrupt_file_falls_back_to_backup() {
+        let dir = test_dir("yaml-backup-fallback");
+        let path = dir.join("shells.yml");
+        std::fs::write(backup_path(&path, 1), shells_file("50.0")).unwrap();
+        std::fs::write(&path, corrupted(&shells_file("60.0"))).unwrap();
+
+        let (configs, problems, corrupt) = read_or_salvage(&path);
+        assert!(corrupt);
+        assert!(!problems.is_empty());
+        // The complete entry is newer than the backup, the cut off one is replaced by it
+        assert_eq!(max_pressure(&configs[&GuildId(1)]), 60.0);
+        assert_eq!(max_pressure(&configs[&GuildId(2)]), 50.0);
+        // Reading never touches the files
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn corrupt_file_without_backup_keeps_what_is_complete() {
+        let dir = test_dir("yaml-no-backup");
+        let path = dir.join("shells.yml");
+        std::fs::write(&path, corrupted(&shells_file("60.0"))).unwrap();
+
+        let (configs, problems) = load_shell_configs(&path);
+        assert_eq!(configs.keys().collect::<Vec<_>>(), vec![&GuildId(1)]);
+        assert!(problems.iter().any(|p| p.contains("No usable backup")));
+        // Moved aside so the next save doesn't mix with the broken contents
+        assert!(!path.exists());
+    }
+}
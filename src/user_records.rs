@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use serenity::model::id::UserId;
+use serenity::model::interactions::application_command::{ApplicationCommandInteraction, ResolvedTarget};
+use serenity::model::interactions::InteractionApplicationCommandCallbackDataFlags;
+use serenity::prelude::SerenityError;
+
+use crate::guild_shell::GuildShell;
+use crate::moderation::user_option;
+use crate::moderation_log::truncate;
+
+/// Name of the user context menu command that shows a member's profile.
+pub const WHOIS_USER_COMMAND: &str = "Bussy profile";
+/// How many joins, cases and notes a profile shows.
+const PROFILE_LIST_LENGTH: usize = 5;
+const EMBED_FIELD_LIMIT: usize = 1024;
+/// Records without moderation history are dropped after this many days without activity.
+const USER_RECORD_RETENTION_DAYS: i64 = 180;
+/// At most this many records are kept per guild, the least recently active are dropped first.
+const MAX_USER_RECORDS: usize = 20_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Note {
+    pub author: UserId,
+    pub text: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A member leaving the guild, by themselves or by being kicked or banned.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Departure {
+    pub timestamp: DateTime<Utc>,
+    pub silenced: bool,
+    /// Whether they were one of the raiders of the raid going on at the time.
+    pub during_raid: bool,
+}
+
+/// Everything bussy remembers about a member of one guild, kept across restarts and after they leave.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct UserRecord {
+    pub joins: Vec<DateTime<Utc>>,
+    pub departures: Vec<Departure>,
+    pub silences: u32,
+    pub warnings: u32,
+    pub raids: u32,
+    pub peak_pressure: f64,
+    pub message_count: u64,
+    pub notes: Vec<Note>,
+    /// When the record last changed. Records from before it was tracked count from the first prune.
+    pub last_activity: Option<DateTime<Utc>>,
+}
+
+impl UserRecord {
+    /// Whether moderators dealt with the member, such records outlive the retention period.
+    fn has_moderation_history(&self) -> bool {
+        self.silences > 0 || self.warnings > 0 || !self.notes.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserRecords {
+    users: HashMap<UserId, UserRecord>,
+}
+
+impl UserRecords {
+    pub fn get(&self, user_id: UserId) -> Option<&UserRecord> {
+        self.users.get(&user_id)
+    }
+
+    pub fn entry(&mut self, user_id: UserId) -> &mut UserRecord {
+        let record = self.users.entry(user_id).or_default();
+        record.last_activity = Some(Utc::now());
+        record
+    }
+
+    /// Drops records without moderation history that were inactive for `USER_RECORD_RETENTION_DAYS`,
+    /// then the least recently active beyond `MAX_USER_RECORDS`, plain records before the others.
+    pub fn prune(&mut self) {
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::days(USER_RECORD_RETENTION_DAYS);
+        self.users.retain(|_, record| {
+            let last_activity = *record.last_activity.get_or_insert(now);
+            last_activity > cutoff || record.has_moderation_history()
+        });
+
+        if self.users.len() > MAX_USER_RECORDS {
+            let mut by_relevance: Vec<(bool, DateTime<Utc>, UserId)> = self.users.iter()
+                .map(|(id, record)| (record.has_moderation_history(), record.last_activity.unwrap_or(now), *id))
+                .collect();
+            by_relevance.sort_unstable();
+            for (_, _, id) in by_relevance.into_iter().take(self.users.len() - MAX_USER_RECORDS) {
+                self.users.remove(&id);
+            }
+        }
+    }
+}
+
+impl GuildShell {
+    /// Handles `/whois` and the profile context menu command.
+    pub(crate) async fn handle_whois(&mut self, ctx: &Context, command: &ApplicationCommandInteraction) -> Result<(), SerenityError> {
+        let user = match &command.data.target {
+            Some(ResolvedTarget::User(user, _)) => Some(user.clone()),
+            _ => user_option(&command.data.options, "user")
+        };
+        let user = match user {
+            Some(user) => user,
+            None => return command.create_interaction_response(&ctx, |resp| {
+                resp.interaction_response_data(|d| d.content("Pick a member to look up.").flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL))
+            }).await
+        };
+
+        let record = self.user_records.get(user.id).cloned().unwrap_or_default();
+        let cases = self.cases.search(Some(user.id), None);
+        let pressure = self.member_pressure(user.id);
+
+        let mut joins = if record.joins.is_empty() {
+            "Not seen joining".to_string()
+        } else {
+            record.joins.iter().rev().take(PROFILE_LIST_LENGTH).map(|joined| format!("<t:{}:f>", joined.timestamp())).collect::<Vec<String>>().join("\n")
+        };
+        let mut notes = if record.notes.is_empty() {
+            "None".to_string()
+        } else {
+            record.notes.iter().rev().take(PROFILE_LIST_LENGTH)
+                .map(|note| format!("<t:{}:d> <@{}>: {}", note.timestamp.timestamp(), note.author, note.text))
+                .collect::<Vec<String>>().join("\n")
+        };
+        let mut recent_cases = if cases.is_empty() {
+            "None".to_string()
+        } else {
+            cases.iter().take(PROFILE_LIST_LENGTH).map(|case| case.summary()).collect::<Vec<String>>().join("\n")
+        };
+        for value in [&mut joins, &mut notes, &mut recent_cases] {
+            truncate(value, EMBED_FIELD_LIMIT);
+        }
+
+        command.create_interaction_response(&ctx, |resp| {
+            resp.interaction_response_data(|d| {
+                d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                    .create_embed(|e| {
+                        e.title(user.tag()).thumbnail(user.face())
+                            .description(format!("<@{}> ({})", user.id, user.id))
+                            .field("Account created", format!("<t:{}:R>", user.created_at().timestamp()), true)
+                            .field("Messages", record.message_count, true)
+                            .field("Silences", record.silences, true)
+                            .field("Warnings", record.warnings, true)
+                            .field("Raids joined", record.raids, true)
+                            .field("Times left", record.departures.len(), true)
+                            .field("Peak pressure", format!("{:.0}", record.peak_pressure), true)
+                            .field("Current pressure", pressure.map_or("Not tracked".to_string(), |p| format!("{:.0}", p)), true)
+                            .field(format!("Joins ({})", record.joins.len()), joins, false)
+                            .field(format!("Cases ({})", cases.len()), recent_cases, false)
+                            .field(format!("Notes ({})", record.notes.len()), notes, false)
+                    })
+            })
+        }).await
+    }
+}